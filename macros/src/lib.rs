@@ -0,0 +1,225 @@
+//! Generates the ast and visitors of a yellow-peach grammar at compile time,
+//! the same items `yp generate --single-file` writes.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::{quote, quote_spanned};
+use syn::{parse_macro_input, File, ItemMod, LitStr};
+use yellow_peach::diagnostic::Diagnostic;
+use yellow_peach::Config;
+
+/// Expands to the ast of an inline grammar, with the visitors nested as
+/// `pub mod visitor` and `pub mod visitor_mut`:
+///
+/// ```ignore
+/// yellow_peach_macros::yp!(r#"
+///     expr
+///         : left=expr "+" right=expr # add
+///         | lit                      # lit
+///         ;
+/// "#);
+/// ```
+#[proc_macro]
+pub fn yp(input: TokenStream) -> TokenStream {
+    let lit = parse_macro_input!(input as LitStr);
+    expand_yp(&lit).into()
+}
+
+fn expand_yp(lit: &LitStr) -> proc_macro2::TokenStream {
+    let offsets = value_offsets(&lit.token().to_string());
+    // errors point into the literal where the compiler supports it
+    let span_of = |d: &Diagnostic| {
+        d.span
+            .and_then(|s| {
+                let start = *offsets.get(s.start)?;
+                let end = *offsets.get(s.end.max(s.start + 1))?;
+                lit.token().subspan(start..end)
+            })
+            .unwrap_or_else(|| lit.span())
+    };
+    let file = match generate(None, &lit.value(), lit.span(), span_of) {
+        Ok(d) => d,
+        Err(e) => return e,
+    };
+    let items = file.items;
+    quote! {
+        #(#items)*
+    }
+}
+
+/// Fills a module with the ast of a grammar file, the path is relative to
+/// the directory of the crate's `Cargo.toml`:
+///
+/// ```ignore
+/// #[yellow_peach_macros::yp_grammar("grammars/sql.yp")]
+/// pub mod ast {}
+/// ```
+#[proc_macro_attribute]
+pub fn yp_grammar(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let lit = parse_macro_input!(attr as LitStr);
+    let module = parse_macro_input!(item as ItemMod);
+
+    let mut path = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap_or_default());
+    path.push(lit.value());
+    let s = match fs::read_to_string(&path) {
+        Ok(d) => d,
+        Err(e) => {
+            let msg = format!("failed to read grammar `{}`: {}", path.display(), e);
+            return compile_error(&msg, lit.span()).into();
+        }
+    };
+    let file = match generate(Some(&path), &s, lit.span(), |_| lit.span()) {
+        Ok(d) => d,
+        Err(e) => return e.into(),
+    };
+
+    let ItemMod {
+        attrs,
+        vis,
+        ident,
+        content,
+        ..
+    } = module;
+    let existing = content.map(|(_, items)| items).unwrap_or_default();
+    // rebuild when the grammar changes
+    let path = path.display().to_string();
+    let items = file.items;
+    let ret = quote! {
+        #(#attrs)*
+        #vis mod #ident {
+            const _: &[u8] = include_bytes!(#path);
+            #(#existing)*
+            #(#items)*
+        }
+    };
+    ret.into()
+}
+
+/// The generated file, or a `compile_error!` per error in the grammar at the
+/// span `span_of` maps it to, other errors are reported at `span`.
+fn generate<F>(
+    path: Option<&PathBuf>,
+    s: &str,
+    span: Span,
+    span_of: F,
+) -> Result<File, proc_macro2::TokenStream>
+where
+    F: Fn(&Diagnostic) -> Span,
+{
+    let mut config = Config::new();
+    config.cargo_metadata(false);
+    if let Some(d) = path {
+        config.grammar(d);
+    }
+    let display = match path {
+        Some(d) => d.display().to_string(),
+        None => "<inline>".to_string(),
+    };
+    let code = config
+        .generate_single_file(s)
+        .map_err(|e| match e.diagnostics() {
+            Some(ds) => ds
+                .iter()
+                .filter(|d| d.is_error())
+                .map(|d| compile_error(&d.render(s, &display), span_of(d)))
+                .collect(),
+            None => compile_error(&e.to_string(), span),
+        })?;
+    syn::parse_str(&code)
+        .map_err(|e| compile_error(&format!("generated invalid code: {}", e), span))
+}
+
+/// Byte offsets into `repr`, the source of a string literal like `"a\nb"`,
+/// of every char of its value followed by the end of the value. An escape is
+/// one char of the value, a line continuation none.
+fn value_offsets(repr: &str) -> Vec<usize> {
+    let (start, raw) = match repr.strip_prefix('r') {
+        Some(rest) => (rest.chars().take_while(|c| *c == '#').count() + 2, true),
+        None => (1, false),
+    };
+    let end = repr.rfind('"').unwrap_or(repr.len()).max(start);
+    let mut ret = vec![];
+    let mut chars = repr[start..end].char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if raw || c != '\\' {
+            ret.push(start + i);
+            continue;
+        }
+        match chars.next() {
+            Some((_, '\n')) | Some((_, '\r')) => {
+                while matches!(chars.peek(), Some((_, c)) if c.is_whitespace()) {
+                    chars.next();
+                }
+            }
+            Some((_, 'x')) => {
+                chars.next();
+                chars.next();
+                ret.push(start + i);
+            }
+            Some((_, 'u')) => {
+                for (_, c) in &mut chars {
+                    if c == '}' {
+                        break;
+                    }
+                }
+                ret.push(start + i);
+            }
+            Some(_) => ret.push(start + i),
+            None => {}
+        }
+    }
+    ret.push(end);
+    ret
+}
+
+fn compile_error(msg: &str, span: Span) -> proc_macro2::TokenStream {
+    quote_spanned! {span=>
+        compile_error!(#msg);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_offsets() {
+        assert_eq!(value_offsets(r#""ab""#), vec![1, 2, 3]);
+        assert_eq!(value_offsets(r##"r#"a"b"#"##), vec![3, 4, 5, 6]);
+        // `\n`, `\u{e9}`, a line continuation, then `b`
+        let repr = "\"\\n\\u{e9}\\\n    b\"";
+        assert_eq!(value_offsets(repr), vec![1, 3, 15, 16]);
+        assert_eq!(value_offsets("\"é\""), vec![1, 3]);
+    }
+
+    #[test]
+    fn test_expand_yp() {
+        let lit = LitStr::new("a : b* ; @leaf b ;", Span::call_site());
+        let d = expand_yp(&lit).to_string();
+        assert!(d.contains("pub struct A"), "{}", d);
+        assert!(d.contains("pub mod visitor"), "{}", d);
+
+        let lit = LitStr::new("@!allow(dead_code) a : b ;", Span::call_site());
+        let d = expand_yp(&lit).to_string();
+        assert!(
+            d.contains("# [allow (dead_code)] # [derive (Clone , Debug)] pub struct A"),
+            "{}",
+            d
+        );
+        assert!(!d.contains("# !"), "{}", d);
+
+        let lit = LitStr::new("a : b ;\nb : C ;", Span::call_site());
+        let d = expand_yp(&lit).to_string();
+        assert!(d.starts_with("compile_error !"), "{}", d);
+        assert!(
+            d.contains("identifier `C` contains uppercase letters"),
+            "{}",
+            d
+        );
+        assert!(d.contains("--> <inline>:2:5"), "{}", d);
+    }
+}
@@ -1,25 +1,50 @@
 use reacto::ast::N;
+use reacto::span::Span;
+
+use crate::util::is_ident;
 
 #[derive(Debug, Clone)]
 pub struct Grammar {
+    pub attrs: Vec<N<Attr>>,
     pub rules: Vec<N<Rule>>,
+    /// Every comment in source order, the rest of the tree has none.
+    pub comments: Vec<Comment>,
 }
 
 #[derive(Debug, Clone)]
 pub struct Rule {
+    pub docs: Vec<N<DocComment>>,
     pub attrs: Vec<N<Attr>>,
     pub name: N<Ident>,
     pub kind: RuleKind,
 }
 
+impl Rule {
+    /// Bodies of all alternatives, variants without a body are skipped.
+    pub fn bodies(&self) -> Vec<&N<RuleBody>> {
+        match &self.kind {
+            RuleKind::Enum(s) => s.iter().filter_map(|b| b.body.as_ref()).collect(),
+            RuleKind::Normal(b) => vec![b],
+            RuleKind::Leaf => vec![],
+        }
+    }
+
+    pub fn has_attr(&self, name: &str) -> bool {
+        self.attrs.iter().any(|a| a.to_str() == name)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum RuleKind {
     Enum(Vec<N<NamedRuleBody>>),
     Normal(N<RuleBody>),
+    /// `@leaf name;`, declares a leaf node without a body.
+    Leaf,
 }
 
 #[derive(Debug, Clone)]
 pub struct NamedRuleBody {
+    pub docs: Vec<N<DocComment>>,
     pub name: N<Ident>,
     pub body: Option<N<RuleBody>>,
 }
@@ -29,21 +54,88 @@ pub struct RuleBody {
     pub body: Vec<N<RuleElement>>,
 }
 
+impl RuleBody {
+    /// Elements that become fields, literals are skipped.
+    pub fn fields(&self) -> Vec<&N<RuleElement>> {
+        self.body.iter().filter(|e| !e.is_literal()).collect()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct RuleElement {
+    pub docs: Vec<N<DocComment>>,
     pub name: Option<N<Ident>>,
-    pub nt: N<Ident>,
+    pub term: Term,
     pub quantifier: Option<N<Quantifier>>,
 }
 
+#[derive(Debug, Clone)]
+pub enum Term {
+    /// A rule, leaf node or std primary type.
+    Ident(N<Ident>),
+    /// `( ... )`, replaced by a reference to a synthesized rule in
+    /// `mir::desugar`.
+    Group(N<Group>),
+    /// `"select"`, concrete syntax without a field in the AST.
+    Literal(N<Literal>),
+}
+
+/// `( a b | c # name )`, one or more alternatives in parentheses. A single
+/// alternative becomes a struct, more become an enum.
+#[derive(Debug, Clone)]
+pub struct Group {
+    pub alts: Vec<N<NamedRuleBody>>,
+}
+
+impl Group {
+    /// Name of the `index`th alternative without `# name`: its only element,
+    /// a literal only if it is a valid name, or `alt{index}`.
+    pub fn default_alt_name(body: &RuleBody, index: usize) -> String {
+        if let [e] = body.body.as_slice() {
+            match (&e.name, &e.term) {
+                (Some(d), _) | (None, Term::Ident(d)) => return d.to_str().to_string(),
+                (None, Term::Literal(d)) if is_ident(&d.text) => return d.text.to_lowercase(),
+                _ => {}
+            }
+        }
+        format!("alt{}", index)
+    }
+}
+
 impl RuleElement {
+    /// The referenced rule or leaf node, only available once groups are
+    /// desugared.
+    pub fn nt(&self) -> &N<Ident> {
+        match &self.term {
+            Term::Ident(d) => d,
+            Term::Group(_) => panic!("group elements must be desugared first"),
+            Term::Literal(_) => panic!("literal elements have no node"),
+        }
+    }
+
+    pub fn is_literal(&self) -> bool {
+        matches!(self.term, Term::Literal(_))
+    }
+
     pub fn has_many(&self) -> bool {
-        if let Some(d) = &self.quantifier {
-            if d.data == Quantifier::Multi || d.data == Quantifier::AtLeastOne {
-                return true;
-            }
+        use Quantifier::*;
+        matches!(
+            self.quantifier.as_ref().map(|d| &d.data),
+            Some(Multi) | Some(AtLeastOne) | Some(MultiSep(_)) | Some(AtLeastOneSep(_))
+        )
+    }
+
+    /// Name of the generated field, before keyword escaping.
+    pub fn field_name(&self) -> String {
+        let name = match &self.name {
+            Some(d) => d.to_str(),
+            None => self.nt().to_str(),
+        };
+        if self.has_many() {
+            format!("{}s", name)
+        } else {
+            name.to_string()
         }
-        false
     }
 }
 
@@ -55,6 +147,10 @@ pub enum Quantifier {
     Multi,
     /// +
     AtLeastOne,
+    /// * % ","
+    MultiSep(String),
+    /// + % "," or % ","
+    AtLeastOneSep(String),
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
@@ -65,6 +161,43 @@ pub struct Ident {
 #[derive(Debug, Clone)]
 pub struct Attr {
     pub name: String,
+    pub args: Vec<N<AttrArg>>,
+}
+
+/// `value` or `key = value` inside the parentheses of an attribute.
+#[derive(Debug, Clone)]
+pub struct AttrArg {
+    pub key: Option<String>,
+    pub value: AttrValue,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum AttrValue {
+    /// An identifier or a path like `serde::Serialize`.
+    Path(String),
+    /// A string literal, unescaped.
+    Str(String),
+}
+
+/// A string literal in a rule body, unescaped.
+#[derive(Debug, Clone)]
+pub struct Literal {
+    pub text: String,
+}
+
+/// A `//` or `/* */` comment, only kept to be reproduced by the formatter.
+#[derive(Debug, Clone)]
+pub struct Comment {
+    pub span: Span,
+    pub text: String,
+    /// Followed by an empty line.
+    pub blank_after: bool,
+}
+
+/// One line of a `///` comment, without the slashes and the space after them.
+#[derive(Debug, Clone)]
+pub struct DocComment {
+    pub text: String,
 }
 
 impl Quantifier {
@@ -72,8 +205,17 @@ impl Quantifier {
         use Quantifier::*;
         match self {
             Maybe => "?",
-            Multi => "*",
-            AtLeastOne => "+",
+            Multi | MultiSep(_) => "*",
+            AtLeastOne | AtLeastOneSep(_) => "+",
+        }
+    }
+
+    /// The separator between repeated elements, unescaped.
+    pub fn separator(&self) -> Option<&str> {
+        use Quantifier::*;
+        match self {
+            MultiSep(d) | AtLeastOneSep(d) => Some(d),
+            Maybe | Multi | AtLeastOne => None,
         }
     }
 }
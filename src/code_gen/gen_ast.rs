@@ -1,65 +1,136 @@
-use iterable::Iterable;
-use itertools::Itertools;
+use proc_macro2::TokenStream;
+use quote::quote;
 
 use crate::ast;
-use crate::util::indent;
 
 use super::*;
 
 impl<'ast> CodeGen<'ast> {
-    pub fn gen_ast(&self) -> String {
-        let body = self.mir.rules.iter().map(|r| self.gen_rule(r)).join("\n\n");
+    pub fn gen_ast(&self) -> syn::Result<String> {
+        let items = self.gen_ast_items()?;
+        self.render(quote! {
+            use reacto::ast::N;
+            #items
+        })
+    }
+
+    /// The ast types, without imports.
+    pub fn gen_ast_items(&self) -> syn::Result<TokenStream> {
+        let rules = self
+            .mir
+            .rules
+            .iter()
+            .map(|r| self.gen_rule(r))
+            .collect::<syn::Result<Vec<_>>>()?;
         let leaf_nodes = self
             .mir
             .leaf_nodes
             .iter()
             .map(|r| self.gen_leaf_node(r))
-            .join("\n\n");
-        let head = "use reacto::ast::N;";
-        format!("{}\n\n{}\n\n{}\n", head, body, leaf_nodes)
+            .collect::<syn::Result<Vec<_>>>()?;
+        Ok(quote! {
+            #(#rules)*
+            #(#leaf_nodes)*
+        })
     }
 
-    fn gen_leaf_node(&self, node: &Ident) -> String {
+    fn gen_leaf_node(&self, node: &Ident) -> syn::Result<TokenStream> {
         let ty_name = self.type_name(node);
-        let ret = format!("pub struct {};", ty_name);
-        format!("#[derive(Clone, Debug, Copy)]\n{}", ret)
+        let attrs = self.gen_type_attrs(node, &["Clone", "Debug", "Copy"])?;
+        Ok(quote! {
+            #attrs
+            pub struct #ty_name;
+        })
     }
 
-    fn gen_rule(&self, rule: &ast::Rule) -> String {
+    fn gen_rule(&self, rule: &ast::Rule) -> syn::Result<TokenStream> {
         use ast::RuleKind::*;
         let ty_name = self.type_name(&rule.name);
-        let ret = match &rule.kind {
+        let item = match &rule.kind {
             Enum(s) => {
-                let body = s.map(|b| self.gen_ast_enum(b)).join(",\n");
-                format!("pub enum {} {{\n{}\n}}", ty_name, indent(&body))
+                let variants = s.iter().map(|b| self.gen_ast_enum(b));
+                quote! {
+                    pub enum #ty_name {
+                        #(#variants),*
+                    }
+                }
             }
             Normal(s) => {
-                let body = self.gen_ast_struct(s);
-                format!("pub struct {} {{\n{}\n}}", ty_name, indent(&body))
+                let fields = s.fields();
+                let fields = fields.iter().map(|e| self.gen_ast_field(e));
+                quote! {
+                    pub struct #ty_name {
+                        #(#fields),*
+                    }
+                }
             }
+            Leaf => unreachable!("leaf declarations are lowered to leaf nodes"),
         };
-        format!("#[derive(Clone, Debug)]\n{}", ret)
+        let docs = gen_docs(&rule.docs);
+        let attrs = self.gen_type_attrs(&rule.name, &["Clone", "Debug"])?;
+        Ok(quote! {
+            #docs
+            #attrs
+            #item
+        })
     }
 
-    fn gen_ast_struct(&self, body: &ast::RuleBody) -> String {
-        (&body.body).lazy_map(|e| self.gen_ast_field(e)).join(",\n")
+    /// `#[derive(..)]` with `derives` and the ones requested in the grammar,
+    /// followed by the raw outer attributes.
+    fn gen_type_attrs(&self, id: &Ident, derives: &[&str]) -> syn::Result<TokenStream> {
+        let attrs = self.mir.type_attrs(id);
+        let mut all = derives.to_vec();
+        for d in &attrs.derives {
+            if !all.contains(&d.as_str()) {
+                all.push(d);
+            }
+        }
+        // both are checked to parse when lowering
+        let derives = all
+            .iter()
+            .map(|d| syn::parse_str::<syn::Path>(d))
+            .collect::<syn::Result<Vec<_>>>()?;
+        let attrs = attrs
+            .attrs
+            .iter()
+            .map(|a| syn::parse_str::<syn::Meta>(a))
+            .collect::<syn::Result<Vec<_>>>()?;
+        Ok(quote! {
+            #[derive(#(#derives),*)]
+            #(#[#attrs])*
+        })
     }
 
-    fn gen_ast_field(&self, ele: &ast::RuleElement) -> String {
+    fn gen_ast_field(&self, ele: &ast::RuleElement) -> TokenStream {
+        let docs = gen_docs(&ele.docs);
+        let name = self.field_name(ele);
         let ty = self.quantifier_type(ele);
+        quote!(#docs pub #name: #ty)
+    }
+
+    // enum variant fields are always public, `pub` is not allowed
+    fn gen_ast_variant_field(&self, ele: &ast::RuleElement) -> TokenStream {
+        let docs = gen_docs(&ele.docs);
         let name = self.field_name(ele);
-        format!("pub {}: {}", name, ty)
+        let ty = self.quantifier_type(ele);
+        quote!(#docs #name: #ty)
     }
 
-    fn gen_ast_enum(&self, body: &ast::NamedRuleBody) -> String {
+    fn gen_ast_enum(&self, body: &ast::NamedRuleBody) -> TokenStream {
+        let docs = gen_docs(&body.docs);
         let variant = self.variant_name(&body.name);
-        if let Some(body) = &body.body {
-            let body = (&body.body)
-                .lazy_map(|r| self.quantifier_type(r))
-                .join(", ");
-            format!("{}({})", variant, body)
+        let body = match &body.body {
+            Some(b) if !b.fields().is_empty() => b,
+            // no body, or only literals
+            _ => return quote!(#docs #variant),
+        };
+        let fields = body.fields();
+        if self.mir.is_named_variant(body) {
+            let fields = fields.iter().map(|e| self.gen_ast_variant_field(e));
+            quote!(#docs #variant { #(#fields),* })
         } else {
-            variant
+            let tys = fields.iter().map(|e| self.quantifier_type(e));
+            quote!(#docs #variant(#(#tys),*))
         }
     }
 }
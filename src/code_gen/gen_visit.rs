@@ -1,236 +1,209 @@
 use indexmap::set::IndexSet;
-use iterable::Iterable;
-use itertools::Itertools;
+use proc_macro2::{Ident as RsIdent, TokenStream};
+use quote::{format_ident, quote};
 use reacto::ast::N;
 
 use crate::ast;
 use crate::ast::Ident;
-use crate::code_gen::CodeGen;
-use crate::util::{indent, trim};
+use crate::code_gen::{gen_docs, CodeGen};
 
 impl<'ast> CodeGen<'ast> {
-    pub fn gen_visit(&self, is_mut: bool) -> String {
-        let body = self
+    pub fn gen_visit(&self, is_mut: bool) -> syn::Result<String> {
+        let items = self.gen_visit_items(is_mut);
+        self.render(quote! {
+            use reacto::ast::N;
+            use crate::ast::*;
+            #items
+        })
+    }
+
+    /// The visitor trait and the walk functions, without imports.
+    pub fn gen_visit_items(&self, is_mut: bool) -> TokenStream {
+        let methods = self
             .mir
             .rules
-            .lazy_map(|r| self.gen_visit_method(r, is_mut))
-            .join("\n\n");
-        let body_leaf = self.gen_visit_method_empty_multi(&self.mir.leaf_nodes, is_mut);
-        let body_reserved = self.gen_visit_method_empty_multi(&self.mir.reserved_nodes, is_mut);
-        let body_std_primary =
+            .iter()
+            .map(|r| self.gen_visit_method(r, is_mut));
+        let methods_leaf = self.gen_visit_method_empty_multi(&self.mir.leaf_nodes, is_mut);
+        let methods_reserved = self.gen_visit_method_empty_multi(&self.mir.reserved_nodes, is_mut);
+        let methods_std_primary =
             self.gen_visit_method_empty_multi(&self.mir.std_primary_nodes, is_mut);
-        let visitor = format!(
-            r#"
-use reacto::ast::N;
-
-use crate::ast::*;
-
-pub trait {}: Sized {{
-{}
-
-{}
-
-{}
-
-{}
-}}
-        "#,
-            visitor_name(is_mut),
-            indent(&body),
-            indent(&body_leaf),
-            indent(&body_reserved),
-            indent(&body_std_primary)
-        );
-
         let walk_methods = self
             .mir
             .rules
-            .lazy_map(|r| self.gen_walk_method(r, is_mut))
-            .join("\n\n");
-
-        format!("{}\n\n{}\n", trim(&visitor), trim(&walk_methods))
+            .iter()
+            .map(|r| self.gen_walk_method(r, is_mut));
+        let visitor = visitor_name(is_mut);
+        quote! {
+            pub trait #visitor: Sized {
+                #(#methods)*
+                #methods_leaf
+                #methods_reserved
+                #methods_std_primary
+            }
+            #(#walk_methods)*
+        }
     }
 
-    fn gen_visit_method(&self, rule: &ast::Rule, is_mut: bool) -> String {
+    fn gen_visit_method(&self, rule: &ast::Rule, is_mut: bool) -> TokenStream {
+        let docs = gen_docs(&rule.docs);
         let visit_name = visit_name(&rule.name);
         let walk_name = walk_name(&rule.name);
-
-        let ty = self.node_type_name(&rule.name);
-        let ret = format!(
-            r#"
-fn {}(&mut self, n: {}) {{
-    {}(self, n);
-}}
-        "#,
-            visit_name,
-            wrap_mut(is_mut, &ty),
-            walk_name
-        );
-        trim(&ret)
+        let ty = wrap_mut(is_mut, self.node_type_name(&rule.name));
+        quote! {
+            #docs
+            fn #visit_name(&mut self, n: #ty) {
+                #walk_name(self, n);
+            }
+        }
     }
 
-    fn gen_visit_method_empty_multi(&self, nodes: &IndexSet<&N<Ident>>, is_mut: bool) -> String {
+    fn gen_visit_method_empty_multi(
+        &self,
+        nodes: &IndexSet<&N<Ident>>,
+        is_mut: bool,
+    ) -> TokenStream {
         nodes
             .iter()
             .map(|n| self.gen_visit_method_empty(n, is_mut))
-            .join("\n\n")
+            .collect()
     }
 
-    fn gen_visit_method_empty(&self, id: &Ident, is_mut: bool) -> String {
+    fn gen_visit_method_empty(&self, id: &Ident, is_mut: bool) -> TokenStream {
         let visit_name = visit_name(id);
-        let ty = self.node_type_name(id);
-        format!(
-            "fn {}(&mut self, _n: {}) {{}}",
-            visit_name,
-            wrap_mut(is_mut, &ty)
-        )
+        let ty = wrap_mut(is_mut, self.node_type_name(id));
+        quote! {
+            fn #visit_name(&mut self, _n: #ty) {}
+        }
     }
 
-    fn gen_walk_method(&self, rule: &ast::Rule, is_mut: bool) -> String {
+    fn gen_walk_method(&self, rule: &ast::Rule, is_mut: bool) -> TokenStream {
         use ast::RuleKind::*;
 
         let walk_name = walk_name(&rule.name);
-        let ty = self.node_type_name(&rule.name);
+        let ty = wrap_mut(is_mut, self.node_type_name(&rule.name));
         let body = match &rule.kind {
             Enum(s) => {
                 let ty_name = self.type_name(&rule.name);
-                let matches = s.lazy_map(|d| self.gen_visit_enum(d)).join("\n");
-                let match_target = deref_mut(self.mir.is_boxed(&rule.name), is_mut, "n");
-                format!(
-                    "use {}::*;\nmatch {} {{\n{}\n}}",
-                    ty_name,
-                    match_target,
-                    indent(&matches)
-                )
-            }
-            Normal(s) => {
-                let prefix = if is_mut { "&mut n." } else { "&n." };
-                self.gen_visit_struct(s, prefix)
+                let arms = s.iter().map(|d| self.gen_visit_enum(&ty_name, d));
+                let target = deref_mut(self.mir.is_boxed(&rule.name), is_mut);
+                // variants are not imported, they could shadow `Some`
+                quote! {
+                    match #target {
+                        #(#arms)*
+                    }
+                }
             }
+            Normal(s) => self.gen_visit_struct(s, is_mut),
+            Leaf => unreachable!("leaf declarations are lowered to leaf nodes"),
         };
-        let ret = format!(
-            r#"
-#[allow(unused)]
-pub fn {}<'ast, V: {}>(v: &mut V, n: {}) {{
-{}
-}}
-        "#,
-            walk_name,
-            visitor_name(is_mut),
-            wrap_mut(is_mut, &ty),
-            indent(&body)
-        );
-        trim(&ret)
+        let visitor = visitor_name(is_mut);
+        quote! {
+            #[allow(unused)]
+            pub fn #walk_name<'ast, V: #visitor>(v: &mut V, n: #ty) {
+                #body
+            }
+        }
     }
 
-    fn gen_visit_enum(&self, body: &ast::NamedRuleBody) -> String {
+    fn gen_visit_enum(&self, ty_name: &RsIdent, body: &ast::NamedRuleBody) -> TokenStream {
         let variant = self.variant_name(&body.name);
-        let ret = if let Some(body) = &body.body {
-            let mut variables = Vec::with_capacity(body.body.len());
-            for i in 0..body.body.len() {
-                variables.push(format!("a{}", i));
-            }
-            let body = self.gen_visit_variable(body, &variables);
-            let match_variables = variables.join(", ");
-            format!(
-                r#"
-{}({}) => {{
-{}
-}},
-            "#,
-                variant,
-                match_variables,
-                indent(&body)
-            )
+        let variant = quote!(#ty_name::#variant);
+        let body = match &body.body {
+            Some(b) if !b.fields().is_empty() => b,
+            // no body, or only literals
+            _ => return quote!(#variant => {}),
+        };
+        let fields = body.fields();
+        let variables: Vec<_> = (0..fields.len()).map(|i| format_ident!("a{}", i)).collect();
+        let pattern = if self.mir.is_named_variant(body) {
+            let names = fields.iter().map(|e| self.field_name(e));
+            quote!(#variant { #(#names: #variables),* })
         } else {
-            format!("{} => {{}},", variant)
+            quote!(#variant(#(#variables),*))
         };
-        trim(&ret)
-    }
-
-    fn gen_visit_variable(&self, body: &ast::RuleBody, variables: &[String]) -> String {
-        assert_eq!(body.body.len(), variables.len());
-        (&body.body)
-            .lazy_zip(variables)
-            .lazy_map(|(e, v)| self.gen_visit_element(e, v))
-            .join("\n")
+        let stmts = fields
+            .iter()
+            .zip(&variables)
+            .map(|(e, v)| self.gen_visit_element(e, quote!(#v)));
+        quote! {
+            #pattern => {
+                #(#stmts)*
+            }
+        }
     }
 
-    fn gen_visit_struct(&self, body: &ast::RuleBody, prefix: &str) -> String {
-        (&body.body)
-            .lazy_map(|e| self.gen_visit_field(e, prefix))
-            .join("\n")
+    fn gen_visit_struct(&self, body: &ast::RuleBody, is_mut: bool) -> TokenStream {
+        body.fields()
+            .iter()
+            .map(|e| self.gen_visit_field(e, is_mut))
+            .collect()
     }
 
-    fn gen_visit_field(&self, ele: &ast::RuleElement, prefix: &str) -> String {
+    fn gen_visit_field(&self, ele: &ast::RuleElement, is_mut: bool) -> TokenStream {
         let field_name = self.field_name(ele);
-        let field_name = format!("{}{}", prefix, field_name);
-        self.gen_visit_element(ele, &field_name)
+        let variable = if is_mut {
+            quote!(&mut n.#field_name)
+        } else {
+            quote!(&n.#field_name)
+        };
+        self.gen_visit_element(ele, variable)
     }
 
-    fn gen_visit_element(&self, ele: &ast::RuleElement, variable: &str) -> String {
+    fn gen_visit_element(&self, ele: &ast::RuleElement, variable: TokenStream) -> TokenStream {
         use ast::Quantifier::*;
-        let visit_name = visit_name(&ele.nt);
-        let ret = if let Some(d) = &ele.quantifier {
+        let visit_name = visit_name(ele.nt());
+        if let Some(d) = &ele.quantifier {
             match &d.data {
-                Maybe => {
-                    format!(
-                        r#"
-if let Some(d) = {} {{
-    v.{}(d);
-}}
-                "#,
-                        variable, visit_name
-                    )
-                }
-                Multi | AtLeastOne => {
-                    format!(
-                        r#"
-for d in {} {{
-    v.{}(d);
-}}
-                "#,
-                        variable, visit_name
-                    )
-                }
+                Maybe => quote! {
+                    if let Some(d) = #variable {
+                        v.#visit_name(d);
+                    }
+                },
+                Multi | AtLeastOne | MultiSep(_) | AtLeastOneSep(_) => quote! {
+                    for d in #variable {
+                        v.#visit_name(d);
+                    }
+                },
             }
         } else {
-            format!("v.{}({});", visit_name, variable)
-        };
-        trim(&ret)
+            quote! {
+                v.#visit_name(#variable);
+            }
+        }
     }
 }
 
-fn walk_name(id: &Ident) -> String {
-    format!("walk_{}", id.to_str())
+fn walk_name(id: &Ident) -> RsIdent {
+    format_ident!("walk_{}", id.to_str())
 }
 
-fn visit_name(id: &Ident) -> String {
-    format!("visit_{}", id.to_str())
+fn visit_name(id: &Ident) -> RsIdent {
+    format_ident!("visit_{}", id.to_str())
 }
 
-fn visitor_name(is_mut: bool) -> &'static str {
+fn visitor_name(is_mut: bool) -> TokenStream {
     if is_mut {
-        "VisitorMut"
+        quote!(VisitorMut)
     } else {
-        "Visitor<'ast>"
+        quote!(Visitor<'ast>)
     }
 }
 
-fn wrap_mut(is_mut: bool, ty: &str) -> String {
+fn wrap_mut(is_mut: bool, ty: TokenStream) -> TokenStream {
     if is_mut {
-        format!("&mut {}", ty)
+        quote!(&mut #ty)
     } else {
-        format!("&'ast {}", ty)
+        quote!(&'ast #ty)
     }
 }
 
-fn deref_mut(is_boxed: bool, is_mut: bool, variable: &str) -> String {
-    let x = match (is_boxed, is_mut) {
-        (false, false) => "&**",
-        (false, true) => "&mut **",
-        (true, false) => "&***",
-        (true, true) => "&mut***",
-    };
-    format!("{}{}", x, variable)
+/// The node `n` points to, a reference through `&N` and the `Box` if any.
+fn deref_mut(is_boxed: bool, is_mut: bool) -> TokenStream {
+    let stars = if is_boxed { quote!(***) } else { quote!(**) };
+    if is_mut {
+        quote!(&mut #stars n)
+    } else {
+        quote!(&#stars n)
+    }
 }
@@ -1,69 +1,312 @@
+use std::str::FromStr;
+
+use proc_macro2::{Ident as RsIdent, TokenStream};
+use quote::{format_ident, quote};
+use reacto::ast::N;
+
 use crate::ast;
 use crate::ast::Ident;
+use crate::mir;
 use crate::mir::Mir;
-use crate::util::{camel_case, is_keyword, is_std_primary};
 
 pub mod gen_ast;
 pub mod gen_visit;
 
+/// `#[doc]` attributes for `docs`, printed as `///` lines.
+fn gen_docs(docs: &[N<ast::DocComment>]) -> TokenStream {
+    let lines = docs.iter().map(|d| {
+        if d.text.is_empty() {
+            String::new()
+        } else {
+            format!(" {}", d.text)
+        }
+    });
+    quote! { #(#[doc = #lines])* }
+}
+
+/// Where generated code comes from, recorded in the `@generated` header of
+/// every file.
+#[derive(Debug, Clone)]
+pub struct Provenance {
+    /// The grammar file, relative to the directory the code is generated for
+    /// so the header does not depend on where yp runs.
+    pub grammar: String,
+    /// `grammar_hash` of the grammar source and the generation options.
+    pub hash: u64,
+}
+
+impl Provenance {
+    pub fn new(
+        grammar: &str,
+        source: &str,
+        options: &mir::Options,
+        emit: &[Artifact],
+        single_file: bool,
+    ) -> Self {
+        let emit: Vec<_> = emit.iter().map(|a| a.name()).collect();
+        let settings = format!(
+            "yp {}\nauto-box={} strict={} single-file={} emit={}\n",
+            env!("CARGO_PKG_VERSION"),
+            options.auto_box,
+            options.strict,
+            single_file,
+            emit.join(",")
+        );
+        Provenance {
+            grammar: grammar.to_string(),
+            hash: grammar_hash(&(settings + source)),
+        }
+    }
+}
+
+const HASH_PREFIX: &str = "// grammar-hash: fnv1a-64 ";
+
+/// 64 bit FNV-1a of a grammar source, stable across platforms.
+pub fn grammar_hash(source: &str) -> u64 {
+    source.bytes().fold(0xcbf2_9ce4_8422_2325, |h, b| {
+        (h ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// The hash in the header of a generated file, compare it with
+/// `Provenance::hash` to tell whether the file is up to date without
+/// generating it again.
+pub fn recorded_hash(generated: &str) -> Option<u64> {
+    generated
+        .lines()
+        .take_while(|l| l.starts_with("//"))
+        .find_map(|l| l.strip_prefix(HASH_PREFIX))
+        .and_then(|h| u64::from_str_radix(h, 16).ok())
+}
+
+/// A file yp can generate.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Artifact {
+    Ast,
+    Visitor,
+    VisitorMut,
+}
+
+impl Artifact {
+    pub const ALL: &'static [Artifact] = &[Artifact::Ast, Artifact::Visitor, Artifact::VisitorMut];
+
+    /// Name used to select the artifact, e.g. `visitor-mut`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Artifact::Ast => "ast",
+            Artifact::Visitor => "visitor",
+            Artifact::VisitorMut => "visitor-mut",
+        }
+    }
+
+    /// Name of the module the artifact is used as.
+    pub fn module_name(self) -> &'static str {
+        match self {
+            Artifact::Ast => "ast",
+            Artifact::Visitor => "visitor",
+            Artifact::VisitorMut => "visitor_mut",
+        }
+    }
+
+    pub fn file_name(self) -> String {
+        format!("{}.rs", self.module_name())
+    }
+}
+
+impl FromStr for Artifact {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Artifact::ALL
+            .iter()
+            .copied()
+            .find(|a| a.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<_> = Artifact::ALL.iter().map(|a| a.name()).collect();
+                format!(
+                    "unknown artifact `{}`, expected one of {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
 pub struct CodeGen<'ast> {
     mir: Mir<'ast>,
+    provenance: Provenance,
 }
 
 impl<'ast> CodeGen<'ast> {
-    pub fn new(mir: Mir<'ast>) -> Self {
-        CodeGen { mir }
+    pub fn new(mir: Mir<'ast>, provenance: Provenance) -> Self {
+        CodeGen { mir, provenance }
     }
 
-    fn node_type_name(&self, input: &Ident) -> String {
-        if self.mir.is_boxed(input) {
-            format!("N<Box<{}>>", self.type_name(input))
-        } else {
-            format!("N<{}>", self.type_name(input))
+    pub fn gen(&self, artifact: Artifact) -> syn::Result<String> {
+        match artifact {
+            Artifact::Ast => self.gen_ast(),
+            Artifact::Visitor => self.gen_visit(false),
+            Artifact::VisitorMut => self.gen_visit(true),
         }
     }
 
-    fn type_name(&self, input: &Ident) -> String {
-        let s = input.to_str();
-        if is_std_primary(s) {
-            s.to_string()
-        } else {
-            camel_case(s)
+    /// The ast with the visitors in `artifacts` nested in it as
+    /// `pub mod visitor { .. }`, the ast itself is always included.
+    pub fn gen_single_file(&self, artifacts: &[Artifact]) -> syn::Result<String> {
+        let mut ret = quote! {
+            use reacto::ast::N;
+        };
+        ret.extend(self.gen_ast_items()?);
+        for a in artifacts {
+            let is_mut = match a {
+                Artifact::Ast => continue,
+                Artifact::Visitor => false,
+                Artifact::VisitorMut => true,
+            };
+            let module = format_ident!("{}", a.module_name());
+            let items = self.gen_visit_items(is_mut);
+            ret.extend(quote! {
+                pub mod #module {
+                    use reacto::ast::N;
+                    use super::*;
+                    #items
+                }
+            });
         }
+        self.render(ret)
     }
 
-    fn variant_name(&self, input: &Ident) -> String {
-        let s = input.to_str();
-        camel_case(s)
+    /// Pretty-prints the items of a generated file after the `@generated`
+    /// header, the output only depends on `items`. Fails if they do not
+    /// parse, which lowering is meant to rule out.
+    fn render(&self, items: TokenStream) -> syn::Result<String> {
+        let lints = self
+            .mir
+            .allow
+            .iter()
+            .map(|l| syn::parse_str::<syn::Path>(l))
+            .collect::<syn::Result<Vec<_>>>()?;
+        let mut file: syn::File = syn::parse2(items)?;
+        // `@!allow` goes on every item, `include!` rejects inner attributes
+        if !lints.is_empty() {
+            let items = file.items.iter().map(|i| {
+                quote! {
+                    #[allow(#(#lints),*)]
+                    #i
+                }
+            });
+            file = syn::parse2(quote!(#(#items)*))?;
+        }
+        Ok(format!(
+            "// @generated by yp from `{}`, do not edit.\n{}{:016x}\n\n{}",
+            self.provenance.grammar,
+            HASH_PREFIX,
+            self.provenance.hash,
+            prettyplease::unparse(&file)
+        ))
     }
 
-    fn field_name(&self, ele: &ast::RuleElement) -> String {
-        let name = match &ele.name {
-            Some(d) => d.to_str(),
-            None => ele.nt.to_str(),
-        };
-        let name = if ele.has_many() {
-            format!("{}s", name)
-        } else {
-            name.to_string()
-        };
-        if is_keyword(&name) {
-            format!("r#{}", name)
+    fn node_type_name(&self, input: &Ident) -> TokenStream {
+        let ty = self.type_name(input);
+        if self.mir.is_boxed(input) {
+            quote!(N<Box<#ty>>)
         } else {
-            name
+            quote!(N<#ty>)
         }
     }
 
-    fn quantifier_type(&self, ele: &ast::RuleElement) -> String {
+    fn type_name(&self, input: &Ident) -> RsIdent {
+        format_ident!("{}", self.mir.type_name(input))
+    }
+
+    fn variant_name(&self, input: &Ident) -> RsIdent {
+        format_ident!("{}", mir::variant_name(input))
+    }
+
+    fn field_name(&self, ele: &ast::RuleElement) -> RsIdent {
+        format_ident!("{}", mir::field_name(ele))
+    }
+
+    fn quantifier_type(&self, ele: &ast::RuleElement) -> TokenStream {
         use ast::Quantifier::*;
-        let ty = self.node_type_name(&ele.nt);
+        let ty = self.node_type_name(ele.nt());
         if let Some(d) = &ele.quantifier {
             match &d.data {
-                Multi | AtLeastOne => format!("Vec<{}>", ty),
-                Maybe => format!("Option<{}>", ty),
+                Multi | AtLeastOne | MultiSep(_) | AtLeastOneSep(_) => quote!(Vec<#ty>),
+                Maybe => quote!(Option<#ty>),
             }
         } else {
             ty
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_recorded_hash() {
+        assert_eq!(0xcbf2_9ce4_8422_2325, grammar_hash(""));
+        assert_eq!(0xaf63_dc4c_8601_ec8c, grammar_hash("a"));
+        let generated = format!(
+            "// @generated by yp from `a.yp`, do not edit.\n{}{:016x}\n\nuse reacto::ast::N;\n",
+            HASH_PREFIX,
+            grammar_hash("a")
+        );
+        assert_eq!(Some(grammar_hash("a")), recorded_hash(&generated));
+        assert_eq!(None, recorded_hash("use reacto::ast::N;\n"));
+    }
+
+    #[test]
+    fn test_labels() {
+        let s = "a : left=b right=b ;";
+        let grammar = Parser::new(Lexer::new(s)).unwrap().parse_grammar().unwrap();
+        let grammar = mir::desugar::desugar(grammar);
+        let provenance = Provenance::new("a.yp", s, &mir::Options::default(), Artifact::ALL, false);
+        let cg = CodeGen::new(mir::lower(&grammar).unwrap(), provenance);
+        let d = cg.gen_single_file(Artifact::ALL).unwrap();
+        assert!(d.contains("pub left: N<B>,"));
+        assert!(!d.contains("Left"));
+        assert!(!d.contains("visit_left"));
+    }
+
+    #[test]
+    fn test_header() {
+        let s = "@!allow(dead_code) a : b ;";
+        let grammar = Parser::new(Lexer::new(s)).unwrap().parse_grammar().unwrap();
+        let grammar = mir::desugar::desugar(grammar);
+        let options = mir::Options::default();
+        let provenance = Provenance::new("../a.yp", s, &options, Artifact::ALL, false);
+        let hash = provenance.hash;
+        let cg = CodeGen::new(mir::lower(&grammar).unwrap(), provenance);
+        let d = cg.gen_ast().unwrap();
+        assert!(d.starts_with("// @generated by yp from `../a.yp`, do not edit.\n"));
+        assert_eq!(recorded_hash(&d), Some(hash));
+        assert!(!d.contains("#!["));
+        assert!(d.contains("\n#[allow(dead_code)]\nuse reacto::ast::N;\n"));
+        assert!(d.contains("\n#[allow(dead_code)]\n#[derive(Clone, Debug)]\npub struct A {"));
+
+        // the options are part of the hash
+        let hashes: Vec<_> = [
+            Provenance::new("a.yp", s, &options, Artifact::ALL, true),
+            Provenance::new("a.yp", s, &options, &[Artifact::Ast], false),
+            Provenance::new(
+                "a.yp",
+                s,
+                &mir::Options {
+                    strict: true,
+                    ..options
+                },
+                Artifact::ALL,
+                false,
+            ),
+        ]
+        .iter()
+        .map(|p| p.hash)
+        .collect();
+        assert!(hashes.iter().all(|h| *h != hash));
+    }
+}
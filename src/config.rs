@@ -0,0 +1,303 @@
+use std::env;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::code_gen::{Artifact, CodeGen, Provenance};
+use crate::diagnostic::Diagnostics;
+use crate::lexer::Lexer;
+use crate::mir;
+use crate::parser::Parser;
+use crate::util::{relative_path, write_if_changed};
+
+/// Generates the ast and visitors of a grammar from a build script:
+///
+/// ```no_run
+/// yellow_peach::Config::new()
+///     .grammar("grammars/sql.yp")
+///     .generate()
+///     .unwrap();
+/// ```
+///
+/// Files go to `OUT_DIR` unless `out_dir` is set, include them with
+/// `include!(concat!(env!("OUT_DIR"), "/ast.rs"))`. The visitors refer to
+/// the ast as `crate::ast`, `single_file` nests them in the ast instead.
+#[derive(Debug, Clone)]
+pub struct Config {
+    grammar: Option<PathBuf>,
+    out_dir: Option<PathBuf>,
+    options: mir::Options,
+    emit: Vec<Artifact>,
+    single_file: bool,
+    cargo_metadata: bool,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// `Config::grammar` was not called.
+    MissingGrammar,
+    /// Neither `Config::out_dir` was called nor `OUT_DIR` is set.
+    MissingOutDir,
+    Io(PathBuf, io::Error),
+    /// The grammar can not be tokenized or parsed, with the diagnostics and
+    /// their rendering.
+    Syntax(PathBuf, Diagnostics, String),
+    /// The grammar is rejected by `mir::lower`, with the diagnostics and
+    /// their rendering.
+    Semantic(PathBuf, Diagnostics, String),
+    /// The generated code does not parse, a bug of `yp` if the grammar was
+    /// accepted.
+    CodeGen(PathBuf, syn::Error),
+}
+
+impl Error {
+    /// What is wrong with the grammar, `None` if it was not read.
+    pub fn diagnostics(&self) -> Option<&Diagnostics> {
+        match self {
+            Error::Syntax(_, d, _) | Error::Semantic(_, d, _) => Some(d),
+            Error::MissingGrammar | Error::MissingOutDir | Error::Io(..) | Error::CodeGen(..) => {
+                None
+            }
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::MissingGrammar => write!(f, "no grammar is given"),
+            Error::MissingOutDir => {
+                write!(f, "no output directory is given and `OUT_DIR` is not set")
+            }
+            Error::Io(path, e) => write!(f, "failed to access `{}`: {}", path.display(), e),
+            Error::Syntax(path, _, d) => {
+                write!(f, "invalid syntax in `{}`:\n{}", path.display(), d)
+            }
+            Error::Semantic(path, _, d) => {
+                write!(f, "invalid grammar `{}`:\n{}", path.display(), d)
+            }
+            Error::CodeGen(path, e) => {
+                write!(f, "failed to generate code for `{}`: {}", path.display(), e)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(_, e) => Some(e),
+            Error::CodeGen(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            grammar: None,
+            out_dir: None,
+            options: mir::Options::default(),
+            emit: Artifact::ALL.to_vec(),
+            single_file: false,
+            cargo_metadata: true,
+        }
+    }
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Config::default()
+    }
+
+    pub fn grammar<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.grammar = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Where to generate files, defaults to `OUT_DIR`.
+    pub fn out_dir<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.out_dir = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Same as `--auto-box` of `yp generate`.
+    pub fn auto_box(&mut self, auto_box: bool) -> &mut Self {
+        self.options.auto_box = auto_box;
+        self
+    }
+
+    /// Same as `--strict` of `yp generate`.
+    pub fn strict(&mut self, strict: bool) -> &mut Self {
+        self.options.strict = strict;
+        self
+    }
+
+    /// Artifacts to generate, all of them by default.
+    pub fn emit(&mut self, artifacts: &[Artifact]) -> &mut Self {
+        self.emit = artifacts.to_vec();
+        self
+    }
+
+    /// Same as `--single-file` of `yp generate`.
+    pub fn single_file(&mut self, single_file: bool) -> &mut Self {
+        self.single_file = single_file;
+        self
+    }
+
+    /// Whether to print `cargo:rerun-if-changed` for the grammar and
+    /// `cargo:warning` for its warnings, on by default.
+    pub fn cargo_metadata(&mut self, cargo_metadata: bool) -> &mut Self {
+        self.cargo_metadata = cargo_metadata;
+        self
+    }
+
+    /// Files already holding the generated code are not written again, so
+    /// cargo does not rebuild what includes them.
+    pub fn generate(&self) -> Result<(), Error> {
+        let path = self.grammar.as_ref().ok_or(Error::MissingGrammar)?;
+        let out_dir = match &self.out_dir {
+            Some(d) => d.clone(),
+            None => env::var_os("OUT_DIR")
+                .map(PathBuf::from)
+                .ok_or(Error::MissingOutDir)?,
+        };
+        if self.cargo_metadata {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+
+        let s = fs::read_to_string(path).map_err(|e| Error::Io(path.clone(), e))?;
+        let provenance = self.provenance(path, &s, Some(&out_dir));
+        self.with_code_gen(path, provenance, &s, |cg| {
+            let write = |file: String, d: String| {
+                let p = out_dir.join(file);
+                write_if_changed(&p, &d)
+                    .map(|_| ())
+                    .map_err(|e| Error::Io(p, e))
+            };
+            let code_gen = |e| Error::CodeGen(path.clone(), e);
+            if self.single_file {
+                let d = cg.gen_single_file(&self.emit).map_err(code_gen)?;
+                write(Artifact::Ast.file_name(), d)
+            } else {
+                for a in &self.emit {
+                    write(a.file_name(), cg.gen(*a).map_err(code_gen)?)?;
+                }
+                Ok(())
+            }
+        })
+    }
+
+    /// The code of grammar `source` as one file, see
+    /// `CodeGen::gen_single_file`. Nothing is read or written, `grammar` only
+    /// names the grammar in diagnostics.
+    pub fn generate_single_file(&self, source: &str) -> Result<String, Error> {
+        let path = match &self.grammar {
+            Some(d) => d.clone(),
+            None => PathBuf::from("<inline>"),
+        };
+        let provenance = self.provenance(&path, source, None);
+        self.with_code_gen(&path, provenance, source, |cg| {
+            cg.gen_single_file(&self.emit)
+                .map_err(|e| Error::CodeGen(path.clone(), e))
+        })
+    }
+
+    /// The grammar is named relative to the package in the headers, or to
+    /// `out_dir` outside of cargo.
+    fn provenance(&self, path: &Path, s: &str, out_dir: Option<&Path>) -> Provenance {
+        let base = env::var_os("CARGO_MANIFEST_DIR").map(PathBuf::from);
+        let grammar = match base.as_deref().or(out_dir) {
+            Some(base) if self.grammar.is_some() => relative_path(path, base),
+            _ => path.display().to_string(),
+        };
+        Provenance::new(&grammar, s, &self.options, &self.emit, self.single_file)
+    }
+
+    fn with_code_gen<T, F>(
+        &self,
+        path: &Path,
+        provenance: Provenance,
+        s: &str,
+        f: F,
+    ) -> Result<T, Error>
+    where
+        F: FnOnce(&CodeGen) -> Result<T, Error>,
+    {
+        let display = path.display().to_string();
+        let grammar = Parser::new(Lexer::new(s))
+            .and_then(|mut parser| parser.parse_grammar())
+            .map_err(|e| {
+                let mut diagnostics = Diagnostics::new();
+                diagnostics.push(e.to_diagnostic());
+                let rendered = diagnostics.render(s, &display);
+                Error::Syntax(path.to_path_buf(), diagnostics, rendered)
+            })?;
+        let grammar = mir::desugar::desugar(grammar);
+        let mir = mir::lower_with(&grammar, &self.options).map_err(|e| {
+            let mut diagnostics = Diagnostics::new();
+            diagnostics.extend(e.iter().map(|e| e.to_diagnostic()));
+            let rendered = diagnostics.render(s, &display);
+            Error::Semantic(path.to_path_buf(), diagnostics, rendered)
+        })?;
+        if self.cargo_metadata && !mir.warnings.is_empty() {
+            let mut diagnostics = Diagnostics::new();
+            diagnostics.extend(mir.warnings.iter().map(|w| w.to_diagnostic()));
+            for line in diagnostics.render(s, &display).lines() {
+                println!("cargo:warning={}", line);
+            }
+        }
+        f(&CodeGen::new(mir, provenance))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let grammar = dir.join("g.yp");
+        fs::write(&grammar, "a : b* ;").unwrap();
+
+        let mut config = Config::new();
+        config.cargo_metadata(false);
+        assert!(matches!(config.generate(), Err(Error::MissingGrammar)));
+        config
+            .grammar(&grammar)
+            .out_dir(dir)
+            .emit(&[Artifact::Ast, Artifact::Visitor]);
+        config.generate().unwrap();
+        assert!(dir.join("ast.rs").exists());
+        assert!(dir.join("visitor.rs").exists());
+        assert!(!dir.join("visitor_mut.rs").exists());
+
+        // a file cut after its header is generated again
+        let ast = fs::read_to_string(dir.join("ast.rs")).unwrap();
+        let header: String = ast.lines().take(2).map(|l| format!("{}\n", l)).collect();
+        let grammar_name = relative_path(&grammar, Path::new(env!("CARGO_MANIFEST_DIR")));
+        assert!(header.contains(&format!("`{}`", grammar_name)));
+        fs::write(dir.join("ast.rs"), &header).unwrap();
+        config.generate().unwrap();
+        assert_eq!(fs::read_to_string(dir.join("ast.rs")).unwrap(), ast);
+
+        let d = config
+            .single_file(true)
+            .generate_single_file("a : b ;")
+            .unwrap();
+        assert!(d.contains("pub mod visitor {"));
+        let e = config.generate_single_file("a : b").unwrap_err();
+        assert!(matches!(e, Error::Syntax(..)));
+        let e = config.generate_single_file("a : A ;").unwrap_err();
+        assert!(matches!(e, Error::Semantic(..)));
+        assert_eq!(e.diagnostics().unwrap().iter().count(), 1);
+        assert!(e
+            .to_string()
+            .starts_with(&format!("invalid grammar `{}`", grammar.display())));
+    }
+}
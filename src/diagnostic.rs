@@ -0,0 +1,209 @@
+use std::cmp::Reverse;
+use std::fmt;
+
+use reacto::span::Span;
+
+use crate::location::Location;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// Extra information attached to a `Diagnostic`, optionally pointing at
+/// another part of the grammar.
+#[derive(Debug, Clone)]
+pub struct Note {
+    pub span: Option<Span>,
+    pub message: String,
+}
+
+/// A problem found in a grammar, detached from the stage that found it so
+/// lex, parse and semantic errors can be reported the same way.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub span: Option<Span>,
+    pub message: String,
+    pub notes: Vec<Note>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics {
+    items: Vec<Diagnostic>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity,
+            span: None,
+            message: message.into(),
+            notes: vec![],
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Diagnostic::new(Severity::Error, message)
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Diagnostic::new(Severity::Warning, message)
+    }
+
+    pub fn with_span(mut self, span: impl Into<Option<Span>>) -> Self {
+        self.span = span.into();
+        self
+    }
+
+    pub fn with_note(mut self, message: impl Into<String>) -> Self {
+        self.notes.push(Note {
+            span: None,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_span_note(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.notes.push(Note {
+            span: Some(span),
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// Renders the diagnostic against the grammar it was found in:
+    ///
+    /// ```text
+    /// error: expected `;`, found `|`
+    ///  --> grammars/sql.yp:3:5
+    ///   |
+    /// 3 |     | query_spec
+    ///   |     ^
+    ///   = note: ...
+    /// ```
+    pub fn render(&self, source: &str, path: &str) -> String {
+        let mut ret = vec![format!("{}: {}", self.severity, self.message)];
+        if let Some(span) = self.span {
+            ret.push(render_location(source, path, span));
+        }
+        for note in &self.notes {
+            match note.span {
+                Some(span) => {
+                    ret.push(format!("note: {}", note.message));
+                    ret.push(render_location(source, path, span));
+                }
+                None => ret.push(format!("  = note: {}", note.message)),
+            }
+        }
+        ret.join("\n")
+    }
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Diagnostics::default()
+    }
+
+    pub fn push(&mut self, d: Diagnostic) {
+        self.items.push(d)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.items.iter().any(|d| d.is_error())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.items.iter()
+    }
+
+    /// Renders all diagnostics followed by a summary line, errors first.
+    pub fn render(&self, source: &str, path: &str) -> String {
+        let mut items: Vec<_> = self.items.iter().collect();
+        items.sort_by_key(|d| Reverse(d.severity));
+        let mut ret: Vec<_> = items.iter().map(|d| d.render(source, path)).collect();
+
+        let errors = self.items.iter().filter(|d| d.is_error()).count();
+        let warnings = self.items.len() - errors;
+        if errors > 0 {
+            ret.push(format!("aborting due to {}", plural(errors, "error")));
+        } else if warnings > 0 {
+            ret.push(format!("generated {}", plural(warnings, "warning")));
+        }
+        ret.join("\n\n")
+    }
+}
+
+impl Extend<Diagnostic> for Diagnostics {
+    fn extend<T: IntoIterator<Item = Diagnostic>>(&mut self, iter: T) {
+        self.items.extend(iter)
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+fn render_location(source: &str, path: &str, span: Span) -> String {
+    let loc = Location::new(source, span);
+    let gutter = " ".repeat(loc.line.to_string().len());
+    format!("{}--> {}:{}\n{}", gutter, path, loc, loc.snippet())
+}
+
+fn plural(n: usize, word: &str) -> String {
+    if n == 1 {
+        format!("{} {}", n, word)
+    } else {
+        format!("{} {}s", n, word)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reacto::lex::Lex;
+
+    use crate::lexer::Lexer;
+
+    #[test]
+    fn test_render() {
+        let source = "a: b;\nb: c;";
+        let tokens = Lexer::new(source).tokens().unwrap();
+        let c = tokens.iter().rev().nth(1).unwrap().span;
+
+        let mut ds = Diagnostics::new();
+        ds.push(Diagnostic::warning("unused rule `a`"));
+        ds.push(Diagnostic::error("undefined rule `c`").with_span(c));
+        assert!(ds.has_errors());
+        assert_eq!(
+            ds.render(source, "g.yp"),
+            "error: undefined rule `c`\n \
+             --> g.yp:2:4\n  |\n2 | b: c;\n  |    ^\n\n\
+             warning: unused rule `a`\n\n\
+             aborting due to 1 error"
+        );
+
+        let mut ds = Diagnostics::new();
+        ds.push(Diagnostic::warning("a").with_note("b"));
+        ds.push(Diagnostic::warning("c"));
+        assert!(!ds.has_errors());
+        assert_eq!(
+            ds.render(source, "g.yp"),
+            "warning: a\n  = note: b\n\nwarning: c\n\ngenerated 2 warnings"
+        );
+    }
+}
@@ -0,0 +1,332 @@
+use itertools::Itertools;
+use reacto::ast::N;
+
+use crate::ast::*;
+use crate::util::escape;
+
+/// Prints `grammar` in the canonical layout: one blank line between rules,
+/// attributes on their own lines, one alternative per line with the `#`
+/// labels of a rule aligned, and comments kept in front of the rule,
+/// alternative or element following them.
+pub fn format_grammar(grammar: &Grammar) -> String {
+    let mut f = Formatter {
+        comments: &grammar.comments,
+        next_comment: 0,
+        out: String::new(),
+    };
+    f.grammar(grammar);
+    f.out
+}
+
+/// Where the elements after a line break start, right after `: `.
+const BODY_INDENT: &str = "      ";
+
+struct Formatter<'a> {
+    comments: &'a [Comment],
+    next_comment: usize,
+    out: String,
+}
+
+impl<'a> Formatter<'a> {
+    fn grammar(&mut self, grammar: &Grammar) {
+        for a in &grammar.attrs {
+            self.comments_before(a.span.start, "");
+            self.line("", &format!("@!{}", attr(a)));
+        }
+        for (i, r) in grammar.rules.iter().enumerate() {
+            if i > 0 || !grammar.attrs.is_empty() {
+                self.out.push('\n');
+            }
+            self.rule(r);
+        }
+        self.comments_before(usize::MAX, "");
+    }
+
+    fn rule(&mut self, rule: &N<Rule>) {
+        self.comments_before(rule.span.start, "");
+        self.docs(&rule.docs, "");
+        for a in &rule.attrs {
+            self.line("", &format!("@{}", attr(a)));
+        }
+        let name = rule.name.to_str();
+        match &rule.kind {
+            RuleKind::Leaf => self.line("", &format!("{};", name)),
+            RuleKind::Normal(b) => {
+                self.line("", name);
+                self.comments_before(b.span.start, "    ");
+                let line = alt_line(":", &self.body(b));
+                self.line("    ", &line);
+            }
+            RuleKind::Enum(alts) => {
+                self.line("", name);
+                // comments and docs in front of each alternative, and its
+                // line, printed once the `#` column is known
+                let mut lines = vec![];
+                for (i, alt) in alts.iter().enumerate() {
+                    let before = self.capture(|f| {
+                        f.comments_before(alt_start(alt), "    ");
+                        f.docs(&alt.docs, "    ");
+                    });
+                    let sep = if i == 0 { ":" } else { "|" };
+                    let body = match &alt.body {
+                        Some(b) => self.body(b),
+                        None => String::new(),
+                    };
+                    lines.push((before, alt_line(sep, &body)));
+                }
+                // element docs break a line, only its last part is aligned
+                let last_width = |l: &String| l.lines().last().unwrap_or("").chars().count();
+                let width = lines.iter().map(|(_, l)| last_width(l)).max().unwrap_or(0);
+                for (alt, (before, line)) in alts.iter().zip(&lines) {
+                    self.out.push_str(before);
+                    let pad = " ".repeat(width - last_width(line));
+                    let line = format!("{}{} # {}", line, pad, alt.name.to_str());
+                    self.line("    ", &line);
+                }
+            }
+        }
+        if let RuleKind::Leaf = rule.kind {
+            return;
+        }
+        self.comments_before(rule.span.end, "    ");
+        self.line("    ", ";");
+    }
+
+    /// Elements separated by spaces. Comments between them and element docs
+    /// go on their own lines, aligned with the first element.
+    fn body(&mut self, b: &RuleBody) -> String {
+        let mut ret = String::new();
+        for (i, e) in b.body.iter().enumerate() {
+            let mut before = vec![];
+            if i > 0 {
+                before = self
+                    .take_comments_before(e.span.start)
+                    .map(|c| c.text.clone())
+                    .collect();
+            }
+            before.extend(e.docs.iter().map(|d| doc(d)));
+            if !before.is_empty() {
+                for l in before {
+                    ret.push('\n');
+                    ret.push_str(BODY_INDENT);
+                    ret.push_str(&l);
+                }
+                ret.push('\n');
+                ret.push_str(BODY_INDENT);
+            } else if i > 0 {
+                ret.push(' ');
+            }
+            ret.push_str(&self.element(e));
+        }
+        ret
+    }
+
+    fn element(&mut self, e: &RuleElement) -> String {
+        let mut ret = String::new();
+        if let Some(d) = &e.name {
+            ret.push_str(&format!("{}=", d.to_str()));
+        }
+        ret.push_str(&self.term(&e.term));
+        if let Some(q) = &e.quantifier {
+            ret.push_str(&quantifier(q));
+        }
+        ret
+    }
+
+    fn term(&mut self, t: &Term) -> String {
+        match t {
+            Term::Ident(d) => d.to_str().to_string(),
+            Term::Literal(d) => format!("\"{}\"", escape(&d.text)),
+            Term::Group(g) => {
+                let mut alts = vec![];
+                for (i, alt) in g.alts.iter().enumerate() {
+                    let b = alt.body.as_ref().expect("group alternatives have a body");
+                    let name = alt.name.to_str();
+                    // only names differing from the default were written
+                    if g.alts.len() > 1 && name != Group::default_alt_name(b, i + 1) {
+                        alts.push(format!("{} # {}", self.body(b), name));
+                    } else {
+                        alts.push(self.body(b));
+                    }
+                }
+                format!("({})", alts.join(" | "))
+            }
+        }
+    }
+
+    fn docs(&mut self, docs: &[N<DocComment>], indent: &str) {
+        for d in docs {
+            self.line(indent, &doc(d));
+        }
+    }
+
+    /// Comments not printed yet that start before `pos`.
+    fn comments_before(&mut self, pos: usize, indent: &str) {
+        while let Some(c) = self.comments.get(self.next_comment) {
+            if c.span.start >= pos {
+                break;
+            }
+            self.line(indent, &c.text);
+            if c.blank_after {
+                self.out.push('\n');
+            }
+            self.next_comment += 1;
+        }
+    }
+
+    /// Like `comments_before`, for comments printed by the caller.
+    fn take_comments_before(&mut self, pos: usize) -> impl Iterator<Item = &'a Comment> {
+        let start = self.next_comment;
+        while let Some(c) = self.comments.get(self.next_comment) {
+            if c.span.start >= pos {
+                break;
+            }
+            self.next_comment += 1;
+        }
+        self.comments[start..self.next_comment].iter()
+    }
+
+    /// What `f` prints, instead of printing it.
+    fn capture<F: FnOnce(&mut Self)>(&mut self, f: F) -> String {
+        let out = std::mem::take(&mut self.out);
+        f(self);
+        std::mem::replace(&mut self.out, out)
+    }
+
+    fn line(&mut self, indent: &str, s: &str) {
+        self.out.push_str(indent);
+        self.out.push_str(s);
+        self.out.push('\n');
+    }
+}
+
+/// Where the alternative starts in the source, `# name` comes last.
+fn alt_start(alt: &N<NamedRuleBody>) -> usize {
+    match (alt.docs.first(), &alt.body) {
+        (Some(d), _) => d.span.start,
+        (None, Some(b)) => b.span.start,
+        (None, None) => alt.name.span.start,
+    }
+}
+
+fn attr(a: &Attr) -> String {
+    if a.args.is_empty() {
+        return a.to_str().to_string();
+    }
+    let args = a
+        .args
+        .iter()
+        .map(|arg| {
+            let value = match &arg.value {
+                AttrValue::Path(d) => d.clone(),
+                AttrValue::Str(d) => format!("\"{}\"", escape(d)),
+            };
+            match &arg.key {
+                Some(k) => format!("{} = {}", k, value),
+                None => value,
+            }
+        })
+        .join(", ");
+    format!("{}({})", a.to_str(), args)
+}
+
+/// `sep` followed by `body`, which may start with a line break.
+fn alt_line(sep: &str, body: &str) -> String {
+    format!("{} {}", sep, body)
+        .lines()
+        .map(|l| l.trim_end())
+        .join("\n")
+}
+
+fn doc(d: &DocComment) -> String {
+    if d.text.is_empty() {
+        "///".to_string()
+    } else {
+        format!("/// {}", d.text)
+    }
+}
+
+fn quantifier(q: &Quantifier) -> String {
+    match q {
+        Quantifier::MultiSep(d) => format!("* % \"{}\"", escape(d)),
+        Quantifier::AtLeastOneSep(d) => format!(" % \"{}\"", escape(d)),
+        _ => q.to_str().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn format(s: &str) -> String {
+        let grammar = Parser::new(Lexer::new(s)).unwrap().parse_grammar().unwrap();
+        format_grammar(&grammar)
+    }
+
+    #[test]
+    fn test_format_grammar() {
+        let s = r#"// header
+
+@box a: b c? #x|#yy
+// between
+| d* % "," # z ;
+e:(f|g "h" # gh)+;"#;
+        let expected = r#"// header
+
+@box
+a
+    : b c?     # x
+    |          # yy
+    // between
+    | d* % "," # z
+    ;
+
+e
+    : (f | g "h" # gh)+
+    ;
+"#;
+        assert_eq!(format(s), expected);
+        assert_eq!(format(expected), expected);
+    }
+
+    #[test]
+    fn test_format_attr_args() {
+        let s = "@derive( a ,b::c=d ) @attr(k=\"v\") a : b ;";
+        assert!(format(s).starts_with("@derive(a, b::c = d)\n@attr(k = \"v\")\na\n"));
+    }
+
+    #[test]
+    fn test_format_body_comments() {
+        let s = r#"a: /// first
+b // after b
+c /* before d */ d;
+e: f # x
+| g
+/// docs of h
+h # y
+// end
+;"#;
+        let expected = r#"a
+    :
+      /// first
+      b
+      // after b
+      c
+      /* before d */
+      d
+    ;
+
+e
+    : f     # x
+    | g
+      /// docs of h
+      h # y
+    // end
+    ;
+"#;
+        assert_eq!(format(s), expected);
+        assert_eq!(format(expected), expected);
+    }
+}
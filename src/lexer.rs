@@ -1,14 +1,18 @@
 use std::error::Error;
 use std::fmt;
+use std::rc::Rc;
 
 use reacto::lex::{Lex, LexCtx};
 use reacto::span::Span;
 
+use crate::diagnostic::Diagnostic;
+use crate::location::Location;
 use crate::token::*;
 
 #[derive(Debug)]
 pub struct LexError {
     span: Option<Span>,
+    location: Option<Box<Location>>,
     kind: LexErrorKind,
 }
 
@@ -17,6 +21,7 @@ enum LexErrorKind {
     NameStartWithDigit(char),
     UnknownChar(char),
     LitStringNotClosed,
+    BlockCommentNotClosed,
 }
 
 pub type Result<T> = std::result::Result<T, LexError>;
@@ -24,17 +29,28 @@ pub type Result<T> = std::result::Result<T, LexError>;
 #[derive(Clone, Debug)]
 pub struct Lexer {
     ctx: LexCtx,
+    source: Rc<str>,
 }
 
 impl Lexer {
     pub fn new(input: &str) -> Self {
         let ctx = LexCtx::new(input);
-        Lexer { ctx }
+        let source = Rc::from(input);
+        Lexer { ctx, source }
+    }
+
+    pub fn source(&self) -> &Rc<str> {
+        &self.source
     }
 
     fn make_error(&mut self, kind: LexErrorKind) -> LexError {
         let span = self.span();
-        LexError { span, kind }
+        let location = span.map(|s| Box::new(Location::new(&self.source, s)));
+        LexError {
+            span,
+            location,
+            kind,
+        }
     }
 }
 
@@ -60,23 +76,58 @@ impl Lex for Lexer {
             '?' => Token::Question,
             '+' => Token::Plus,
             '*' => Token::Asterisk,
+            '%' => Token::Percent,
             ':' => Token::Colon,
             ';' => Token::Semicolon,
             '#' => Token::NumSign,
             '|' => Token::Alt,
             '=' => Token::Assign,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            ',' => Token::Comma,
             ' ' => Token::Whitespace(Whitespace::Space),
             '\n' => Token::Whitespace(Whitespace::Newline),
             '\r' => Token::Whitespace(Whitespace::CarriageReturn),
             '\t' => Token::Whitespace(Whitespace::HorizontalTab),
+            '/' if self.advance_cmp('/') => {
+                // `///` starts a doc comment, `////` is a plain comment again
+                let is_doc = self.advance_cmp('/') && !self.advance_cmp('/');
+                self.advance_while(|c| c != '\n');
+                if is_doc {
+                    Token::DocComment
+                } else {
+                    Token::Comment(Comment::Line)
+                }
+            }
+            '/' if self.advance_cmp('*') => {
+                loop {
+                    match self.advance() {
+                        Some('*') if self.advance_cmp('/') => break,
+                        Some(_) => {}
+                        None => return Err(self.make_error(LexErrorKind::BlockCommentNotClosed)),
+                    }
+                }
+                Token::Comment(Comment::Block)
+            }
             '@' => {
+                let is_inner = self.advance_cmp('!');
                 self.advance_while(is_digit_letter);
-                Token::Attr
+                if is_inner {
+                    Token::InnerAttr
+                } else {
+                    Token::Attr
+                }
             }
             '"' => {
-                self.advance_while(|c| c != '"');
-                if !self.advance_cmp('"') {
-                    return Err(self.make_error(LexErrorKind::LitStringNotClosed));
+                loop {
+                    match self.advance() {
+                        Some('\\') => {
+                            self.advance();
+                        }
+                        Some('"') => break,
+                        Some(_) => {}
+                        None => return Err(self.make_error(LexErrorKind::LitStringNotClosed)),
+                    }
                 }
                 Token::LitString
             }
@@ -91,15 +142,44 @@ impl Lex for Lexer {
     }
 }
 
+impl LexError {
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
+
+    pub fn location(&self) -> Option<&Location> {
+        self.location.as_deref()
+    }
+
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        Diagnostic::error(self.kind.to_string()).with_span(self.span)
+    }
+}
+
+impl fmt::Display for LexErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use LexErrorKind::*;
+        match self {
+            NameStartWithDigit(c) => write!(f, "name can not start with digit `{}`", c),
+            UnknownChar(c) => write!(f, "unknown character `{}`", c.escape_default()),
+            LitStringNotClosed => write!(f, "string literal is not closed"),
+            BlockCommentNotClosed => write!(f, "block comment is not closed"),
+        }
+    }
+}
+
 impl fmt::Display for LexError {
-    fn fmt(&self, _f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        todo!()
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.location {
+            Some(loc) => write!(f, "{}: {}\n{}", loc, self.kind, loc.snippet()),
+            None => write!(f, "{}", self.kind),
+        }
     }
 }
 
 impl Error for LexError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
-        todo!()
+        None
     }
 }
 
@@ -107,13 +187,36 @@ impl Error for LexError {
 // helper functions
 
 fn is_digit(c: char) -> bool {
-    c >= '0' && c <= '9'
+    c.is_ascii_digit()
 }
 
 fn is_letter(c: char) -> bool {
-    (c >= 'a' && c <= 'z') || (c >= 'A' && c <= 'Z') || (c == '_')
+    c.is_ascii_alphabetic() || c == '_'
 }
 
 fn is_digit_letter(c: char) -> bool {
     is_digit(c) || is_letter(c)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(s: &str) -> Vec<Token> {
+        let mut lexer = Lexer::new(s);
+        lexer.tokens().unwrap().into_iter().map(|t| t.tok).collect()
+    }
+
+    #[test]
+    fn test_comment() {
+        let line = Token::Comment(Comment::Line);
+        let block = Token::Comment(Comment::Block);
+        let nl = Token::Whitespace(Whitespace::Newline);
+        assert_eq!(tokens("// a: b;\na"), vec![line, nl, Token::Ident]);
+        assert_eq!(tokens("/* a\n**/a"), vec![block, Token::Ident]);
+        assert_eq!(tokens("/// a"), vec![Token::DocComment]);
+        assert_eq!(tokens("//// a"), vec![line]);
+        assert!(Lexer::new("/* a").tokens().is_err());
+        assert!(Lexer::new("/ a").tokens().is_err());
+    }
+}
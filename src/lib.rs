@@ -0,0 +1,20 @@
+//! Generates rust ast types and visitors from a grammar.
+//!
+//! The `yp` binary and `Config`, meant for build scripts, share the same
+//! pipeline: `lexer::Lexer` → `parser::Parser` → `mir::desugar` →
+//! `mir::lower` → `code_gen::CodeGen`.
+
+pub mod ast;
+pub mod code_gen;
+pub mod config;
+pub mod diagnostic;
+pub mod formatter;
+pub mod lexer;
+pub mod location;
+pub mod mir;
+pub mod parser;
+pub mod token;
+pub mod util;
+pub mod visit;
+
+pub use crate::config::{Config, Error};
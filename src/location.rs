@@ -0,0 +1,106 @@
+use std::fmt;
+
+use reacto::span::Span;
+
+/// A `Span` resolved against the grammar source, used to point at the
+/// offending part of a grammar in error messages.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Location {
+    /// 1-based line number.
+    pub line: usize,
+    /// 1-based column number, counted in chars.
+    pub column: usize,
+    /// Number of chars to underline, at least 1.
+    pub len: usize,
+    /// The whole source line the span starts on, without the line break.
+    pub text: String,
+}
+
+impl Location {
+    pub fn new(source: &str, span: Span) -> Self {
+        Location::from_offsets(source, span.start, span.end)
+    }
+
+    fn from_offsets(source: &str, start: usize, end: usize) -> Self {
+        let mut line = 1;
+        let mut line_start = 0;
+        for (i, c) in source.chars().enumerate() {
+            if i >= start {
+                break;
+            }
+            if c == '\n' {
+                line += 1;
+                line_start = i + 1;
+            }
+        }
+        let text: String = source
+            .chars()
+            .skip(line_start)
+            .take_while(|c| *c != '\n')
+            .collect();
+        let text = text.trim_end_matches('\r').to_string();
+        let column = start - line_start + 1;
+        // a span crossing the line end is only underlined up to it
+        let rest = text.chars().count().saturating_sub(column - 1);
+        let len = end.saturating_sub(start).min(rest).max(1);
+        Location {
+            line,
+            column,
+            len,
+            text,
+        }
+    }
+
+    /// Renders the source line with a caret underline below the span:
+    ///
+    /// ```text
+    ///   |
+    /// 3 |     : expr bin_op expr # binary
+    ///   |       ^^^^
+    /// ```
+    pub fn snippet(&self) -> String {
+        let line_no = self.line.to_string();
+        let gutter = " ".repeat(line_no.len());
+        // keep tabs so the carets line up with the quoted text
+        let pad: String = self
+            .text
+            .chars()
+            .take(self.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        format!(
+            "{} |\n{} | {}\n{} | {}{}",
+            gutter,
+            line_no,
+            self.text,
+            gutter,
+            pad,
+            "^".repeat(self.len)
+        )
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_offsets() {
+        let src = "query\n    : query_spec order_by?\n    ;\n";
+        let loc = Location::from_offsets(src, 12, 22);
+        assert_eq!(loc.line, 2);
+        assert_eq!(loc.column, 7);
+        assert_eq!(loc.len, 10);
+        assert_eq!(loc.text, "    : query_spec order_by?");
+        assert_eq!(
+            loc.snippet(),
+            "  |\n2 |     : query_spec order_by?\n  |       ^^^^^^^^^^"
+        );
+    }
+}
@@ -2,70 +2,493 @@ use std::fs;
 use std::io;
 use std::path::Path;
 use std::path::PathBuf;
+use std::str::FromStr;
 
+use reacto::ast::N;
+use reacto::lex::Lex;
+use structopt::clap;
 use structopt::StructOpt;
 
-use crate::code_gen::CodeGen;
-use crate::lexer::Lexer;
-use crate::parser::Parser;
-
-pub mod ast;
-pub mod code_gen;
-pub mod lexer;
-pub mod mir;
-pub mod parser;
-pub mod token;
-pub mod util;
-pub mod visit;
+use yellow_peach::ast::Grammar;
+use yellow_peach::code_gen::{recorded_hash, Artifact, CodeGen, Provenance};
+use yellow_peach::diagnostic::Diagnostics;
+use yellow_peach::formatter;
+use yellow_peach::lexer::Lexer;
+use yellow_peach::location::Location;
+use yellow_peach::mir;
+use yellow_peach::mir::desugar::Desugared;
+use yellow_peach::mir::graph::RuleGraph;
+use yellow_peach::mir::Mir;
+use yellow_peach::parser::Parser;
+use yellow_peach::token::Token;
+use yellow_peach::util::{relative_path, unified_diff, write_if_changed};
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "yp", about = "An ast generator.")]
-struct Opt {
+enum Opt {
+    /// Report the diagnostics of a grammar without generating anything
+    Check(LowerOpt),
+    /// Generate the ast and visitors of a grammar
+    Generate(GenerateOpt),
+    /// Format grammar files in place
+    Fmt(FmtOpt),
+    /// Print the dependency graph between rules in Graphviz dot format
+    Graph(LowerOpt),
+    /// Print an intermediate stage of a grammar for debugging
+    Dump(DumpOpt),
+}
+
+/// Options of every subcommand lowering a grammar.
+#[derive(Debug, StructOpt)]
+struct LowerOpt {
     /// Grammar file path
     #[structopt(parse(from_os_str))]
     grammar_path: PathBuf,
 
+    /// Box recursive rules automatically instead of requiring `@box`
+    #[structopt(long)]
+    auto_box: bool,
+
+    /// Reject undefined rules instead of turning them into leaf nodes
+    #[structopt(long)]
+    strict: bool,
+}
+
+impl LowerOpt {
+    fn options(&self) -> mir::Options {
+        mir::Options {
+            auto_box: self.auto_box,
+            strict: self.strict,
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct GenerateOpt {
+    #[structopt(flatten)]
+    lower: LowerOpt,
+
     /// Where to generate files
     #[structopt(parse(from_os_str))]
     #[structopt(short, default_value = ".")]
     out_dir: PathBuf,
+
+    /// Artifacts to generate, each `name` or `name=file`, names are ast,
+    /// visitor and visitor-mut
+    #[structopt(long, use_delimiter = true, default_value = "ast,visitor,visitor-mut")]
+    emit: Vec<Emit>,
+
+    /// Print the generated code instead of writing files, only one artifact
+    /// or `--single-file` can be printed
+    #[structopt(long)]
+    stdout: bool,
+
+    /// Generate one file, the ast with the visitors nested in it as modules
+    #[structopt(long)]
+    single_file: bool,
+
+    /// Only report generated files that are out of date with the grammar,
+    /// printing the difference, without writing them
+    #[structopt(long, conflicts_with = "stdout")]
+    check: bool,
+}
+
+/// `name` or `name=file` given to `--emit`.
+#[derive(Debug)]
+struct Emit {
+    artifact: Artifact,
+    file: Option<PathBuf>,
+}
+
+impl Emit {
+    fn file(&self) -> PathBuf {
+        match &self.file {
+            Some(d) => d.clone(),
+            None => PathBuf::from(self.artifact.file_name()),
+        }
+    }
+}
+
+impl GenerateOpt {
+    /// Combinations of options clap can not express.
+    fn check_conflicts(&self) -> Result<(), clap::Error> {
+        let conflict = |msg: &str| {
+            Err(clap::Error::with_description(
+                msg,
+                clap::ErrorKind::ArgumentConflict,
+            ))
+        };
+        if self.single_file {
+            // the visitors are nested in the ast file
+            if self
+                .emit
+                .iter()
+                .any(|e| e.artifact != Artifact::Ast && e.file.is_some())
+            {
+                return conflict(
+                    "`--single-file` generates one file, only `--emit ast=file` can name it",
+                );
+            }
+        } else if self.stdout && self.emit.len() != 1 {
+            return conflict("`--stdout` prints one artifact, select it with `--emit`");
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Emit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, file) = match s.find('=') {
+            Some(i) => (&s[..i], Some(PathBuf::from(&s[i + 1..]))),
+            None => (s, None),
+        };
+        let artifact = name.parse()?;
+        Ok(Emit { artifact, file })
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct FmtOpt {
+    /// Grammar file paths
+    #[structopt(parse(from_os_str), required = true)]
+    grammar_paths: Vec<PathBuf>,
+
+    /// Only report files that are not formatted, without writing them
+    #[structopt(long)]
+    check: bool,
+}
+
+#[derive(Debug, StructOpt)]
+struct DumpOpt {
+    #[structopt(flatten)]
+    lower: LowerOpt,
+
+    /// Stage to print
+    #[structopt(long, possible_values = &["tokens", "ast", "mir"])]
+    stage: Stage,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Stage {
+    Tokens,
+    Ast,
+    Mir,
+}
+
+impl FromStr for Stage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tokens" => Ok(Stage::Tokens),
+            "ast" => Ok(Stage::Ast),
+            "mir" => Ok(Stage::Mir),
+            _ => Err(format!("unknown stage `{}`", s)),
+        }
+    }
 }
 
+/// Exit code of `fmt --check` when a file is not formatted, and of
+/// `generate --check` when a generated file is out of date.
+const EXIT_CHECK: i32 = 1;
+/// Exit code when reading the grammar or writing generated files fails.
+const EXIT_IO: i32 = 2;
+/// Exit code when the grammar can not be tokenized or parsed.
+const EXIT_SYNTAX: i32 = 3;
+/// Exit code when the grammar parses but is rejected by `mir::lower`.
+const EXIT_SEMANTIC: i32 = 4;
+
 fn main() {
-    let opt = Opt::from_args();
+    let ret = match Opt::from_args() {
+        Opt::Check(opt) => check(&opt),
+        Opt::Generate(opt) => generate(&opt),
+        Opt::Fmt(opt) => fmt(&opt),
+        Opt::Graph(opt) => graph(&opt),
+        Opt::Dump(opt) => dump(&opt),
+    };
+    if let Err(code) = ret {
+        std::process::exit(code)
+    }
+}
 
-    let s = std::fs::read_to_string(&opt.grammar_path).expect("read grammar filed failed");
-    let lexer = Lexer::new(&s);
-    let mut parser = Parser::new(lexer).expect("create parser failed");
-    let grammar = parser.parse_grammar().expect("parse grammar failed");
-    let mir = mir::lower(&grammar).expect("lower ast to mir failed");
-    let cg = CodeGen::new(mir);
+fn read_grammar(path: &Path) -> Result<String, i32> {
+    fs::read_to_string(path).map_err(|e| {
+        eprintln!("error: failed to read grammar `{}`: {}", path.display(), e);
+        EXIT_IO
+    })
+}
+
+fn parse_grammar(s: &str, path: &str) -> Result<N<Grammar>, i32> {
+    let lexer = Lexer::new(s);
+    Parser::new(lexer)
+        .and_then(|mut parser| parser.parse_grammar())
+        .map_err(|e| {
+            let mut diagnostics = Diagnostics::new();
+            diagnostics.push(e.to_diagnostic());
+            eprintln!("{}", diagnostics.render(s, path));
+            EXIT_SYNTAX
+        })
+}
+
+/// Lowers a desugared `grammar` read from `s`, printing the diagnostics.
+fn lower<'ast>(opt: &LowerOpt, s: &str, grammar: &'ast Desugared) -> Result<Mir<'ast>, i32> {
+    let path = opt.grammar_path.display().to_string();
+    let mut diagnostics = Diagnostics::new();
+    let mir = match mir::lower_with(grammar, &opt.options()) {
+        Ok(d) => d,
+        Err(e) => {
+            diagnostics.extend(e.iter().map(|e| e.to_diagnostic()));
+            eprintln!("{}", diagnostics.render(s, &path));
+            return Err(EXIT_SEMANTIC);
+        }
+    };
+    diagnostics.extend(mir.warnings.iter().map(|w| w.to_diagnostic()));
+    if !diagnostics.is_empty() {
+        eprintln!("{}", diagnostics.render(s, &path));
+    }
+    Ok(mir)
+}
+
+fn check(opt: &LowerOpt) -> Result<(), i32> {
+    let s = read_grammar(&opt.grammar_path)?;
+    let grammar = parse_grammar(&s, &opt.grammar_path.display().to_string())?;
+    let grammar = mir::desugar::desugar(grammar);
+    lower(opt, &s, &grammar).map(|_| ())
+}
 
-    let out_dir = &opt.out_dir;
-    create_ast(&cg, out_dir).expect("create ast file failed");
-    create_visitor(&cg, out_dir).expect("create visitor file failed");
-    create_visitor_mut(&cg, out_dir).expect("create visitor_mut file failed");
+fn generate(opt: &GenerateOpt) -> Result<(), i32> {
+    if let Err(e) = opt.check_conflicts() {
+        e.exit()
+    }
+    let path = opt.lower.grammar_path.display().to_string();
+    let s = read_grammar(&opt.lower.grammar_path)?;
+    let grammar = parse_grammar(&s, &path)?;
+    let grammar = mir::desugar::desugar(grammar);
+    let mir = lower(&opt.lower, &s, &grammar)?;
+    let artifacts: Vec<_> = opt.emit.iter().map(|e| e.artifact).collect();
+    let provenance = Provenance::new(
+        &relative_path(&opt.lower.grammar_path, &opt.out_dir),
+        &s,
+        &opt.lower.options(),
+        &artifacts,
+        opt.single_file,
+    );
+    let cg = CodeGen::new(mir, provenance);
 
-    println!("generate success.")
+    let mut up_to_date = true;
+    if opt.single_file {
+        let file = match opt.emit.iter().find(|e| e.artifact == Artifact::Ast) {
+            Some(e) => e.file(),
+            None => PathBuf::from(Artifact::Ast.file_name()),
+        };
+        let d = cg
+            .gen_single_file(&artifacts)
+            .map_err(|e| gen_failed("ast", e))?;
+        up_to_date &= write_artifact(opt, "ast", &file, &d)?;
+    } else {
+        for e in &opt.emit {
+            let d = cg
+                .gen(e.artifact)
+                .map_err(|err| gen_failed(e.artifact.name(), err))?;
+            up_to_date &= write_artifact(opt, e.artifact.name(), &e.file(), &d)?;
+        }
+    }
+    if !up_to_date {
+        eprintln!("error: generated files are out of date with `{}`", path);
+        return Err(EXIT_CHECK);
+    }
+    if !opt.stdout && !opt.check {
+        println!("generate success.")
+    }
+    Ok(())
 }
 
-fn create_ast<P: AsRef<Path>>(cg: &CodeGen, p: P) -> io::Result<()> {
-    let d = cg.gen_ast();
-    let mut p = p.as_ref().to_path_buf();
-    p.push("ast.rs");
-    fs::write(p, &d)
+/// Writes `d` to `file` unless it already holds it, with `--check` prints the
+/// difference instead. Returns whether `file` was up to date.
+fn write_artifact(opt: &GenerateOpt, name: &str, file: &Path, d: &str) -> Result<bool, i32> {
+    if opt.stdout {
+        print!("{}", d);
+        return Ok(true);
+    }
+    let path = opt.out_dir.join(file);
+    if !opt.check {
+        return write_if_changed(&path, d)
+            .map(|_| true)
+            .map_err(|e| write_failed(name, e));
+    }
+    let old = match fs::read_to_string(&path) {
+        Ok(d) => d,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            eprintln!("error: failed to read `{}`: {}", path.display(), e);
+            return Err(EXIT_IO);
+        }
+    };
+    if old == d {
+        return Ok(true);
+    }
+    print!("{}", unified_diff(&path.display().to_string(), &old, d));
+    // same grammar and options, the file itself was changed
+    if recorded_hash(&old) == recorded_hash(d) {
+        eprintln!(
+            "note: `{}` was edited after it was generated",
+            path.display()
+        );
+    }
+    Ok(false)
 }
 
-fn create_visitor<P: AsRef<Path>>(cg: &CodeGen, p: P) -> io::Result<()> {
-    let d = cg.gen_visit(false);
-    let mut p = p.as_ref().to_path_buf();
-    p.push("visitor.rs");
-    fs::write(p, &d)
+fn graph(opt: &LowerOpt) -> Result<(), i32> {
+    let s = read_grammar(&opt.grammar_path)?;
+    let grammar = parse_grammar(&s, &opt.grammar_path.display().to_string())?;
+    let grammar = mir::desugar::desugar(grammar);
+    let mir = lower(opt, &s, &grammar)?;
+    let graph = RuleGraph::new(&grammar);
+    print!(
+        "{}",
+        graph.to_dot(|r| mir.boxed_rules.iter().any(|b| b.to_str() == r))
+    );
+    Ok(())
 }
 
-fn create_visitor_mut<P: AsRef<Path>>(cg: &CodeGen, p: P) -> io::Result<()> {
-    let d = cg.gen_visit(true);
-    let mut p = p.as_ref().to_path_buf();
-    p.push("visitor_mut.rs");
-    fs::write(p, &d)
+fn dump(opt: &DumpOpt) -> Result<(), i32> {
+    let path = opt.lower.grammar_path.display().to_string();
+    let s = read_grammar(&opt.lower.grammar_path)?;
+    match opt.stage {
+        Stage::Tokens => {
+            let tokens = Lexer::new(&s).tokens().map_err(|e| {
+                let mut diagnostics = Diagnostics::new();
+                diagnostics.push(e.to_diagnostic());
+                eprintln!("{}", diagnostics.render(&s, &path));
+                EXIT_SYNTAX
+            })?;
+            let chars: Vec<char> = s.chars().collect();
+            for t in tokens {
+                if let Token::Whitespace(_) = t.tok {
+                    continue;
+                }
+                let text: String = chars[t.span.start..t.span.end].iter().collect();
+                let location = Location::new(&s, t.span);
+                println!("{}\t{:?}\t{:?}", location, t.tok, text);
+            }
+        }
+        Stage::Ast => {
+            let grammar = parse_grammar(&s, &path)?;
+            println!("{:#?}", grammar);
+        }
+        Stage::Mir => {
+            let grammar = parse_grammar(&s, &path)?;
+            let grammar = mir::desugar::desugar(grammar);
+            let mir = lower(&opt.lower, &s, &grammar)?;
+            println!("{:#?}", mir);
+        }
+    }
+    Ok(())
+}
+
+/// Formats every file even if some of them fail, the exit code is the one of
+/// the last failure.
+fn fmt(opt: &FmtOpt) -> Result<(), i32> {
+    let mut ret = Ok(());
+    for p in &opt.grammar_paths {
+        if let Err(code) = fmt_file(p, opt.check) {
+            ret = Err(code);
+        }
+    }
+    ret
+}
+
+fn fmt_file(p: &Path, check: bool) -> Result<(), i32> {
+    let path = p.display().to_string();
+    let s = read_grammar(p)?;
+    let grammar = parse_grammar(&s, &path)?;
+    let formatted = formatter::format_grammar(&grammar);
+    if formatted == s {
+        return Ok(());
+    }
+    if check {
+        eprintln!("error: `{}` is not formatted", path);
+        return Err(EXIT_CHECK);
+    }
+    fs::write(p, formatted).map_err(|e| {
+        eprintln!("error: failed to write `{}`: {}", path, e);
+        EXIT_IO
+    })
+}
+
+fn write_failed(name: &str, e: io::Error) -> i32 {
+    eprintln!("error: failed to create {} file: {}", name, e);
+    EXIT_IO
+}
+
+// lowering rejects what does not generate valid Rust, this is a bug
+fn gen_failed(name: &str, e: syn::Error) -> i32 {
+    eprintln!("error: failed to generate {} file: {}", name, e);
+    EXIT_SEMANTIC
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory holding only `grammar.yp` with `grammar`, removed when
+    /// dropped.
+    fn temp_dir(grammar: &str) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("grammar.yp"), grammar).unwrap();
+        dir
+    }
+
+    fn generate_opt(dir: &Path, args: &[&str]) -> GenerateOpt {
+        let grammar = dir.join("grammar.yp");
+        let out_dir = dir.display().to_string();
+        let mut argv = vec!["yp", "generate", grammar.to_str().unwrap(), "-o", &out_dir];
+        argv.extend(args);
+        match Opt::from_iter_safe(argv).unwrap() {
+            Opt::Generate(d) => d,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_emit() {
+        let dir = temp_dir("a : b ;");
+        let dir = dir.path();
+        let opt = generate_opt(dir, &["--emit", "ast=syntax.rs,visitor"]);
+        let files: Vec<_> = opt.emit.iter().map(|e| e.file()).collect();
+        assert_eq!(
+            files,
+            vec![PathBuf::from("syntax.rs"), PathBuf::from("visitor.rs")]
+        );
+        assert!(opt.check_conflicts().is_ok());
+        assert_eq!(generate(&opt), Ok(()));
+        assert!(dir.join("syntax.rs").exists());
+        assert!(dir.join("visitor.rs").exists());
+        assert!(!dir.join("visitor_mut.rs").exists());
+
+        let opt = generate_opt(dir, &["--emit", "ast,visitor", "--stdout"]);
+        assert!(opt.check_conflicts().is_err());
+        let opt = generate_opt(dir, &["--single-file", "--emit", "ast=all.rs,visitor"]);
+        assert!(opt.check_conflicts().is_ok());
+        let opt = generate_opt(dir, &["--single-file", "--emit", "ast,visitor=v.rs"]);
+        assert!(opt.check_conflicts().is_err());
+        assert!("tree".parse::<Emit>().is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let dir = temp_dir("a : b ;");
+        let dir = dir.path();
+        let check = generate_opt(dir, &["--emit", "ast", "--check"]);
+        // missing files are stale too
+        assert_eq!(generate(&check), Err(EXIT_CHECK));
+        assert!(!dir.join("ast.rs").exists());
+        assert_eq!(generate(&generate_opt(dir, &["--emit", "ast"])), Ok(()));
+        assert_eq!(generate(&check), Ok(()));
+
+        fs::write(dir.join("grammar.yp"), "a : b c ;").unwrap();
+        assert_eq!(generate(&check), Err(EXIT_CHECK));
+    }
 }
@@ -1,29 +1,385 @@
 use indexmap::map::IndexMap;
 use indexmap::set::IndexSet;
 use iterable::Iterable;
+use itertools::Itertools;
 use reacto::ast::N;
 
 use crate::ast;
 use crate::ast::{Grammar, Ident};
-use crate::util::is_std_primary;
-use crate::visit::{walk_rule, Visitor};
+use crate::diagnostic::Diagnostic;
+use crate::util::{
+    camel_case, did_you_mean, is_renamed, is_std_primary, is_underscore, rust_ident,
+    rust_type_ident, snake_case,
+};
+use crate::visit::{walk_grammar, walk_rule, walk_rule_element, Visitor};
+
+use self::attr::{
+    AttrSpec, Target, ATTR_ALLOW, ATTR_ATTR, ATTR_BOX, ATTR_DERIVE, ATTR_LEAF, ATTR_NAMED_VARIANTS,
+    ATTR_RENAME, ATTR_ROOT,
+};
+use self::desugar::Desugared;
+use self::graph::RuleGraph;
+
+pub mod attr;
+pub mod desugar;
+pub mod graph;
 
 #[derive(Debug, Clone)]
 pub struct Mir<'ast> {
     pub boxed_rules: Vec<&'ast N<Ident>>,
     pub rule_map: IndexMap<String, &'ast N<ast::Rule>>,
-    pub rules: &'ast Vec<N<ast::Rule>>,
+    pub rules: Vec<&'ast N<ast::Rule>>,
     pub leaf_nodes: IndexSet<&'ast N<Ident>>,
     pub reserved_nodes: IndexSet<&'ast N<ast::Ident>>,
     pub std_primary_nodes: IndexSet<&'ast N<ast::Ident>>,
+    /// Type names given by `@rename`, keyed by rule name.
+    pub renames: IndexMap<String, String>,
+    /// `@!derive` and `@!attr` of the grammar, applying to every type.
+    pub global_attrs: TypeAttrs,
+    /// `@derive` and `@attr` of rules and leaf declarations, keyed by name.
+    pub type_attrs: IndexMap<String, TypeAttrs>,
+    /// `@!named_variants`, generate struct-like variants for every
+    /// alternative with a body.
+    pub named_variants: bool,
+    /// Lints of `@!allow`, allowed on every generated item.
+    pub allow: Vec<String>,
+    pub warnings: Vec<Warning<'ast>>,
+}
+
+/// Derives and outer attributes requested for a generated type, in addition
+/// to the ones yp always emits.
+#[derive(Debug, Clone, Default)]
+pub struct TypeAttrs {
+    /// Paths to derive, e.g. `serde::Serialize`.
+    pub derives: Vec<String>,
+    /// Outer attributes without `#[]`, e.g. `non_exhaustive`.
+    pub attrs: Vec<String>,
+}
+
+impl TypeAttrs {
+    fn add(&mut self, a: &ast::Attr) {
+        if a.to_str() == ATTR_DERIVE {
+            for arg in &a.args {
+                if let ast::AttrValue::Path(p) = &arg.value {
+                    self.add_derive(p);
+                }
+            }
+        } else if a.to_str() == ATTR_ATTR {
+            self.attrs.push(attr::str_arg(a).unwrap().to_string());
+        }
+    }
+
+    fn add_derive(&mut self, p: &str) {
+        if !self.derives.iter().any(|d| d == p) {
+            self.derives.push(p.to_string());
+        }
+    }
+
+    fn merge(&mut self, other: &TypeAttrs) {
+        for d in &other.derives {
+            self.add_derive(d);
+        }
+        self.attrs.extend(other.attrs.iter().cloned());
+    }
+}
+
+/// Options of `lower_with`.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Box the rules needed to break infinitely sized recursive types instead
+    /// of reporting them.
+    pub auto_box: bool,
+    /// Reject references to undefined rules instead of turning them into leaf
+    /// nodes. Implied when the grammar declares any `@leaf`.
+    pub strict: bool,
 }
 
 #[derive(Debug, Clone)]
 pub enum Error<'ast> {
-    BasicCheckError(Vec<&'ast N<Ident>>, Vec<&'ast N<ast::Attr>>),
+    /// An identifier with uppercase letters; generated names are derived from
+    /// snake case identifiers.
+    UppercaseIdent(&'ast N<Ident>),
+    /// An identifier made of underscores only, no generated name can be
+    /// derived from it.
+    UnderscoreIdent(&'ast N<Ident>),
+    /// An attribute not in `attr::ATTRS`.
+    UnknownAttr(&'ast N<ast::Attr>),
+    /// An attribute with arguments not matching its spec.
+    InvalidAttrArgs(&'ast N<ast::Attr>, &'static AttrSpec),
+    /// An attribute used where its spec does not allow, e.g. a rule attribute
+    /// used as `@!name` on the grammar.
+    MisplacedAttr(&'ast N<ast::Attr>, &'static AttrSpec),
+    /// A rule defined more than once, (first, duplicate).
+    DuplicatedRule(&'ast N<ast::Rule>, &'ast N<ast::Rule>),
+    /// Two alternatives of a rule mapping to the same variant name,
+    /// (rule, first, duplicate).
+    DuplicatedVariant(
+        &'ast N<ast::Rule>,
+        &'ast N<ast::NamedRuleBody>,
+        &'ast N<ast::NamedRuleBody>,
+    ),
+    /// Two elements of a rule mapping to the same field name,
+    /// (rule, first, duplicate).
+    DuplicatedField(
+        &'ast N<ast::Rule>,
+        &'ast N<ast::RuleElement>,
+        &'ast N<ast::RuleElement>,
+    ),
+    /// A group label used more than once in a rule, (first, duplicate).
+    DuplicatedLabel(&'ast N<Ident>, &'ast N<Ident>),
+    /// Rules or nodes generating the same type name, e.g. because of a
+    /// `@rename`, (first, duplicate, type name).
+    DuplicatedTypeName(&'ast N<Ident>, &'ast N<Ident>, String),
+    /// Rules containing each other by value without a `@box` in between.
+    InfiniteSize(Vec<&'ast N<ast::Rule>>),
+    /// A `@leaf` rule with a body, or a rule without body missing `@leaf`.
+    InvalidLeafDecl(&'ast N<ast::Rule>),
+    /// A reference to an undefined rule in strict mode, with the closest
+    /// defined rule.
+    UndefinedRule(&'ast N<Ident>, Option<&'ast str>),
+    /// An empty literal, or one with a label or a quantifier, (element,
+    /// literal).
+    InvalidLiteral(&'ast N<ast::RuleElement>, &'ast N<ast::Literal>),
+}
+
+#[derive(Debug, Clone)]
+pub enum Warning<'ast> {
+    /// A rule not reachable from any root rule.
+    UnusedRule(&'ast N<ast::Rule>),
+    /// A `@leaf` declaration not reachable from any root rule, (attribute,
+    /// declaration).
+    UnusedLeafDecl(&'ast N<ast::Attr>, &'ast N<ast::Rule>),
+    /// A leaf node referenced once with a name close to a rule, (leaf, rule).
+    SuspiciousLeaf(&'ast N<Ident>, &'ast str),
+    /// A generated name renamed because Rust does not accept it, not even as a
+    /// raw identifier, (origin, kind, name, generated name).
+    RenamedIdent(&'ast N<Ident>, &'static str, String, String),
+}
+
+impl<'ast> Warning<'ast> {
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        match self {
+            Warning::UnusedRule(rule) => {
+                Diagnostic::warning(format!("rule `{}` is never used", rule.name.to_str()))
+                    .with_span(rule.name.span)
+                    .with_note(format!(
+                        "rules are used when reachable from the first rule or a rule marked `@{}`",
+                        ATTR_ROOT
+                    ))
+            }
+            Warning::UnusedLeafDecl(attr, rule) => Diagnostic::warning(format!(
+                "leaf node `{}` is declared but never used",
+                rule.name.to_str()
+            ))
+            .with_span(attr.span)
+            .with_span_note(rule.name.span, "declared here"),
+            Warning::SuspiciousLeaf(leaf, rule) => Diagnostic::warning(format!(
+                "`{}` is not a rule and becomes a leaf node",
+                leaf.to_str()
+            ))
+            .with_span(leaf.span)
+            .with_note(format!("help: did you mean `{}`?", rule)),
+            Warning::RenamedIdent(id, kind, name, ident) => {
+                Diagnostic::warning(format!("{} `{}` can not be used in Rust", kind, name))
+                    .with_span(id.span)
+                    .with_note(format!("it is generated as `{}`", ident))
+            }
+        }
+    }
+}
+
+impl<'ast> Error<'ast> {
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        match self {
+            Error::UppercaseIdent(id) => Diagnostic::error(format!(
+                "identifier `{}` contains uppercase letters",
+                id.to_str()
+            ))
+            .with_span(id.span)
+            .with_note(format!(
+                "help: use snake case: `{}`",
+                snake_case(id.to_str())
+            )),
+            Error::UnderscoreIdent(id) => Diagnostic::error(format!(
+                "identifier `{}` can not be used as a name",
+                id.to_str()
+            ))
+            .with_span(id.span)
+            .with_note("help: use a name with a letter in it"),
+            Error::UnknownAttr(attr) => {
+                let allowed = attr::names().map(|a| format!("`@{}`", a)).join(", ");
+                let d = Diagnostic::error(format!("unknown attribute `@{}`", attr.to_str()))
+                    .with_span(attr.span)
+                    .with_note(format!("allowed attributes are: {}", allowed));
+                match did_you_mean(attr.to_str(), attr::names()) {
+                    Some(s) => d.with_note(format!("help: did you mean `@{}`?", s)),
+                    None => d,
+                }
+            }
+            Error::MisplacedAttr(attr, spec) => {
+                let (msg, help) = if spec.target == Target::Grammar {
+                    (
+                        "only applies to the whole grammar",
+                        "put it at the top of the grammar",
+                    )
+                } else {
+                    (
+                        "can not apply to the whole grammar",
+                        "put it before the rule it applies to",
+                    )
+                };
+                Diagnostic::error(format!("attribute `@{}` {}", attr.to_str(), msg))
+                    .with_span(attr.span)
+                    .with_note(format!("help: {}, e.g. `{}`", help, spec.usage))
+            }
+            Error::InvalidAttrArgs(attr, spec) => {
+                Diagnostic::error(format!("invalid arguments for `@{}`", attr.to_str()))
+                    .with_span(attr.span)
+                    .with_note(format!("usage: `{}`", spec.usage))
+            }
+            Error::DuplicatedRule(first, dup) => Diagnostic::error(format!(
+                "rule `{}` is defined multiple times",
+                dup.name.to_str()
+            ))
+            .with_span(dup.name.span)
+            .with_span_note(first.name.span, "first defined here"),
+            Error::DuplicatedVariant(rule, first, dup) => Diagnostic::error(format!(
+                "variant `{}` of `{}` is defined multiple times",
+                variant_name(&dup.name),
+                rule.name.to_str()
+            ))
+            .with_span(dup.name.span)
+            .with_span_note(first.name.span, "first defined here"),
+            Error::DuplicatedField(rule, first, dup) => Diagnostic::error(format!(
+                "field `{}` of `{}` is defined multiple times",
+                field_name(dup),
+                rule.name.to_str()
+            ))
+            .with_span(dup.span)
+            .with_span_note(first.span, "first defined here")
+            .with_note(format!(
+                "help: give one of them a label, e.g. `other_{0}={0}`",
+                dup.nt().to_str()
+            )),
+            Error::DuplicatedLabel(first, dup) => Diagnostic::error(format!(
+                "duplicate label `{}`",
+                dup.to_str()
+            ))
+            .with_span(dup.span)
+            .with_span_note(first.span, "first used here")
+            .with_note(
+                "labels of groups name the rules made from them, they must be unique within a rule",
+            ),
+            Error::DuplicatedTypeName(first, dup, name) => Diagnostic::error(format!(
+                "type `{}` of `{}` is already generated for `{}`",
+                name,
+                dup.to_str(),
+                first.to_str()
+            ))
+            .with_span(dup.span)
+            .with_span_note(first.span, "first generated here")
+            .with_note(format!(
+                "help: give one of them another name with `@{}(\"...\")`",
+                ATTR_RENAME
+            )),
+            Error::InfiniteSize(rules) => {
+                let names = rules
+                    .iter()
+                    .map(|r| format!("`{}`", r.name.to_str()))
+                    .join(", ");
+                let msg = if rules.len() == 1 {
+                    format!("recursive rule {} has infinite size", names)
+                } else {
+                    format!("recursive rules {} have infinite size", names)
+                };
+                let mut d = Diagnostic::error(msg).with_span(rules[0].name.span);
+                for r in &rules[1..] {
+                    d = d.with_span_note(r.name.span, "also part of the cycle");
+                }
+                d.with_note(format!(
+                    "help: add `@{}` to one of them, or let yp choose with `--auto-box`",
+                    ATTR_BOX
+                ))
+            }
+            Error::InvalidLeafDecl(rule) => {
+                let name = rule.name.to_str();
+                let (msg, help) = match rule.kind {
+                    ast::RuleKind::Leaf => (
+                        format!("rule `{}` has no body", name),
+                        format!("help: declare a leaf node with `@{} {};`", ATTR_LEAF, name),
+                    ),
+                    _ => (
+                        format!("leaf node `{}` has a body", name),
+                        format!("help: remove `@{}` to make it a rule", ATTR_LEAF),
+                    ),
+                };
+                Diagnostic::error(msg)
+                    .with_span(rule.name.span)
+                    .with_note(help)
+            }
+            Error::InvalidLiteral(ele, lit) => {
+                let msg = if lit.text.is_empty() {
+                    "empty literal".to_string()
+                } else if ele.name.is_some() {
+                    format!("literal {:?} can not have a label", lit.text)
+                } else {
+                    format!("literal {:?} can not have a quantifier", lit.text)
+                };
+                Diagnostic::error(msg)
+                    .with_span(ele.span)
+                    .with_note("literals only describe the concrete syntax, they have no field")
+            }
+            Error::UndefinedRule(id, suggestion) => {
+                let d = Diagnostic::error(format!("undefined rule `{}`", id.to_str()))
+                    .with_span(id.span);
+                match suggestion {
+                    Some(s) => d.with_note(format!("help: did you mean `{}`?", s)),
+                    None => d.with_note(format!(
+                        "help: declare it as a leaf node with `@{} {};`",
+                        ATTR_LEAF,
+                        id.to_str()
+                    )),
+                }
+            }
+        }
+    }
 }
 
 impl<'ast> Mir<'ast> {
+    /// Derives and attributes of the type generated for `id`, the grammar
+    /// wide ones first.
+    pub fn type_attrs(&self, id: &Ident) -> TypeAttrs {
+        let mut ret = self.global_attrs.clone();
+        if let Some(d) = self.type_attrs.get(id.to_str()) {
+            ret.merge(d);
+        }
+        ret
+    }
+
+    /// Whether the variant of `body` has named fields instead of a tuple.
+    pub fn is_named_variant(&self, body: &ast::RuleBody) -> bool {
+        self.named_variants || has_labels(body)
+    }
+
+    pub fn renamed(&self, id: &Ident) -> Option<&str> {
+        self.renames.get(id.to_str()).map(|s| s.as_str())
+    }
+
+    /// Name of the type generated for rule or node `id`.
+    pub fn type_name(&self, id: &Ident) -> String {
+        let s = id.to_str();
+        if let Some(d) = self.renamed(id) {
+            rust_type_ident(d)
+        } else if is_std_primary(s) {
+            s.to_string()
+        } else if RESERVED.contains(&s) {
+            // the std type it stands for
+            camel_case(s)
+        } else {
+            rust_type_ident(&camel_case(s))
+        }
+    }
+
     pub fn is_boxed(&self, id: &Ident) -> bool {
         (&self.boxed_rules)
             .find(|r| r.to_str() == id.to_str())
@@ -31,63 +387,374 @@ impl<'ast> Mir<'ast> {
     }
 }
 
-pub fn lower(grammar: &N<ast::Grammar>) -> Result<Mir<'_>, Error<'_>> {
+/// Name of the variant generated for alternative `id`.
+pub fn variant_name(id: &Ident) -> String {
+    rust_ident(&camel_case(id.to_str()))
+}
+
+/// Name of the field generated for `ele`.
+pub fn field_name(ele: &ast::RuleElement) -> String {
+    rust_ident(&ele.field_name())
+}
+
+pub fn lower(grammar: &Desugared) -> Result<Mir<'_>, Vec<Error<'_>>> {
+    lower_with(grammar, &Options::default())
+}
+
+/// Check `grammar` and collect what code generation needs.
+pub fn lower_with<'ast>(
+    desugared: &'ast Desugared,
+    options: &Options,
+) -> Result<Mir<'ast>, Vec<Error<'ast>>> {
+    let grammar: &'ast N<Grammar> = desugared;
     // basic check
     let mut bc = BasicCheck::new();
     bc.visit_grammar(grammar);
-    bc.into_error()?;
+    let mut errors = bc.errors;
+    errors.extend(
+        desugared
+            .duplicated_labels()
+            .iter()
+            .map(|(first, dup)| Error::DuplicatedLabel(first, dup)),
+    );
+    errors.extend(check_duplicated_rules(grammar));
+    let has_leaf_decl = grammar.rules.iter().any(|r| r.has_attr(ATTR_LEAF));
+    if options.strict || has_leaf_decl {
+        errors.extend(check_undefined(grammar));
+    }
+    let mut nc = NameCheck::new();
+    nc.visit_grammar(grammar);
+    errors.extend(nc.errors);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
 
     let mut builder = MirBuilder::new(grammar);
     builder.visit_grammar(grammar);
 
-    let ret = builder.build();
+    // infinite size check, needs the `@box` rules collected by the builder
+    let graph = RuleGraph::new(grammar);
+    let mut boxed: IndexSet<&'ast str> = builder.boxed_rules.iter().map(|r| r.to_str()).collect();
+    if options.auto_box {
+        // synthesized rules have no name in the grammar to put `@box` on
+        for name in graph.minimal_boxing(&boxed, |r| !desugared.is_synthesized(r)) {
+            let rule: &'ast N<ast::Rule> = graph.rules[name];
+            builder.boxed_rules.push(&rule.name);
+            boxed.insert(name);
+        }
+    }
+    let cycles = graph.infinite_cycles(&boxed);
+    if !cycles.is_empty() {
+        let errors = cycles
+            .into_iter()
+            .map(|c| Error::InfiniteSize(c.into_iter().map(|r| graph.rules[r]).collect()))
+            .collect();
+        return Err(errors);
+    }
+
+    let mut ret = builder.build();
+    let errors = check_type_names(grammar, &ret);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    ret.warnings = check_unused(grammar, &graph, &ret);
+    ret.warnings.extend(check_renamed(grammar, &ret));
     Ok(ret)
 }
 
 // basic check
 #[derive(Debug, Clone)]
 struct BasicCheck<'ast> {
-    invalid_attrs: Vec<&'ast N<ast::Attr>>,
-    invalid_ids: Vec<&'ast N<Ident>>,
+    errors: Vec<Error<'ast>>,
 }
 
-static ATTR_BOX: &str = "box";
-static ALLOWED_ATTRS: &[&str] = &[ATTR_BOX];
-
 static RESERVED: &[&str] = &["string"];
 
 impl<'ast> BasicCheck<'ast> {
     fn new() -> Self {
-        BasicCheck {
-            invalid_attrs: vec![],
-            invalid_ids: vec![],
+        BasicCheck { errors: vec![] }
+    }
+}
+
+impl<'ast> Visitor<'ast> for BasicCheck<'ast> {
+    fn visit_grammar(&mut self, n: &'ast N<Grammar>) {
+        for a in &n.attrs {
+            if let Some(spec) = attr::lookup(a.to_str()) {
+                if spec.target == Target::Rule {
+                    self.errors.push(Error::MisplacedAttr(a, spec))
+                }
+            }
         }
+        walk_grammar(self, n)
     }
 
-    fn into_error(self) -> Result<(), Error<'ast>> {
-        if self.invalid_ids.is_empty() && self.invalid_attrs.is_empty() {
-            Ok(())
-        } else {
-            Err(Error::BasicCheckError(self.invalid_ids, self.invalid_attrs))
+    fn visit_rule(&mut self, n: &'ast N<ast::Rule>) {
+        let is_leaf = matches!(n.kind, ast::RuleKind::Leaf);
+        if is_leaf != n.has_attr(ATTR_LEAF) {
+            self.errors.push(Error::InvalidLeafDecl(n))
+        }
+        for a in &n.attrs {
+            if let Some(spec) = attr::lookup(a.to_str()) {
+                if spec.target == Target::Grammar {
+                    self.errors.push(Error::MisplacedAttr(a, spec))
+                }
+            }
         }
+        walk_rule(self, n)
     }
-}
 
-impl<'ast> Visitor<'ast> for BasicCheck<'ast> {
+    fn visit_rule_element(&mut self, n: &'ast N<ast::RuleElement>) {
+        if let ast::Term::Literal(lit) = &n.term {
+            if lit.text.is_empty() || n.name.is_some() || n.quantifier.is_some() {
+                self.errors.push(Error::InvalidLiteral(n, lit))
+            }
+        }
+        walk_rule_element(self, n)
+    }
+
+    // the lexer only accepts `a-zA-Z0-9_` in names and rejects a leading
+    // digit, so uppercase letters and `_` are the only things left to check
     fn visit_ident(&mut self, n: &'ast N<Ident>) {
-        let s = n
-            .to_str()
-            .chars()
-            .all(|c| c == '_' || c.is_lowercase() || c.is_numeric());
-        if !s {
-            self.invalid_ids.push(n)
+        if n.to_str().chars().any(|c| c.is_uppercase()) {
+            self.errors.push(Error::UppercaseIdent(n))
+        } else if is_underscore(n.to_str()) {
+            self.errors.push(Error::UnderscoreIdent(n))
         }
     }
 
     fn visit_attr(&mut self, n: &'ast N<ast::Attr>) {
-        let name = n.to_str();
-        if !ALLOWED_ATTRS.contains(&name) {
-            self.invalid_attrs.push(n)
+        match attr::lookup(n.to_str()) {
+            Some(spec) if !spec.args.accepts(&n.args) => {
+                self.errors.push(Error::InvalidAttrArgs(n, spec))
+            }
+            Some(_) => {}
+            None => self.errors.push(Error::UnknownAttr(n)),
+        }
+    }
+}
+
+fn has_attr(attrs: &[N<ast::Attr>], name: &str) -> bool {
+    attrs.iter().any(|a| a.to_str() == name)
+}
+
+/// Any labeled element, e.g. `left=relation`, makes a variant struct-like.
+fn has_labels(body: &ast::RuleBody) -> bool {
+    body.fields().iter().any(|e| e.name.is_some())
+}
+
+fn check_duplicated_rules(grammar: &N<Grammar>) -> Vec<Error<'_>> {
+    let mut errors = vec![];
+    let mut defined: IndexMap<&str, &N<ast::Rule>> = IndexMap::new();
+    for r in &grammar.rules {
+        match defined.get(r.name.to_str()) {
+            Some(first) => errors.push(Error::DuplicatedRule(first, r)),
+            None => {
+                defined.insert(r.name.to_str(), r);
+            }
+        }
+    }
+    errors
+}
+
+fn check_undefined(grammar: &N<Grammar>) -> Vec<Error<'_>> {
+    let defined: IndexSet<&str> = grammar.rules.iter().map(|r| r.name.to_str()).collect();
+    let mut errors = vec![];
+    for rule in &grammar.rules {
+        for body in rule.bodies() {
+            for e in body.fields() {
+                let name = e.nt().to_str();
+                if defined.contains(name) || is_std_primary(name) || RESERVED.contains(&name) {
+                    continue;
+                }
+                let suggestion = did_you_mean(name, defined.iter().copied());
+                errors.push(Error::UndefinedRule(e.nt(), suggestion));
+            }
+        }
+    }
+    errors
+}
+
+fn check_unused<'ast>(
+    grammar: &'ast N<Grammar>,
+    graph: &RuleGraph<'ast>,
+    mir: &Mir<'ast>,
+) -> Vec<Warning<'ast>> {
+    let mut warnings = vec![];
+
+    let mut roots: Vec<_> = grammar
+        .rules
+        .iter()
+        .filter(|r| r.has_attr(ATTR_ROOT))
+        .map(|r| r.name.to_str())
+        .collect();
+    if roots.is_empty() {
+        roots.extend(grammar.rules.first().map(|r| r.name.to_str()));
+    }
+    let reachable = graph.reachable(&roots);
+    for (name, rule) in &graph.rules {
+        if reachable.contains(name) {
+            continue;
+        }
+        match rule.attrs.iter().find(|a| a.to_str() == ATTR_LEAF) {
+            Some(attr) => warnings.push(Warning::UnusedLeafDecl(attr, rule)),
+            None => warnings.push(Warning::UnusedRule(rule)),
+        }
+    }
+
+    let mut leaf_refs: IndexMap<&str, Vec<&N<Ident>>> = IndexMap::new();
+    for rule in &grammar.rules {
+        for body in rule.bodies() {
+            for e in body.fields() {
+                // declared leaf nodes are intentional
+                if mir.leaf_nodes.contains(e.nt()) && !graph.rules.contains_key(e.nt().to_str()) {
+                    leaf_refs.entry(e.nt().to_str()).or_default().push(e.nt());
+                }
+            }
+        }
+    }
+    for (name, refs) in leaf_refs {
+        if refs.len() != 1 {
+            continue;
+        }
+        if let Some(s) = did_you_mean(name, graph.rules.keys().copied()) {
+            warnings.push(Warning::SuspiciousLeaf(refs[0], s));
+        }
+    }
+    warnings
+}
+
+/// Type names must be unique once renamed and escaped, `@rename("Expr")`
+/// next to rule `expr` would generate two `Expr`.
+fn check_type_names<'ast>(grammar: &'ast N<Grammar>, mir: &Mir<'ast>) -> Vec<Error<'ast>> {
+    let mut errors = vec![];
+    let mut defined: IndexMap<String, &'ast N<Ident>> = IndexMap::new();
+    let nodes = grammar
+        .rules
+        .iter()
+        .map(|r| &r.name)
+        .chain(mir.leaf_nodes.iter().copied())
+        .chain(mir.reserved_nodes.iter().copied())
+        .chain(mir.std_primary_nodes.iter().copied());
+    for id in nodes {
+        let name = mir.type_name(id);
+        match defined.get(&name) {
+            // the same node referenced again, or a declared leaf
+            Some(first) if first.to_str() == id.to_str() => {}
+            Some(first) => errors.push(Error::DuplicatedTypeName(first, id, name)),
+            None => {
+                defined.insert(name, id);
+            }
+        }
+    }
+    errors
+}
+
+/// Types, variants and fields renamed by code generation, see
+/// `util::rust_ident`.
+fn check_renamed<'ast>(grammar: &'ast N<Grammar>, mir: &Mir<'ast>) -> Vec<Warning<'ast>> {
+    let mut warnings = vec![];
+    let mut check = |id: &'ast N<Ident>, kind, name: String, ident: String| {
+        if is_renamed(&name, &ident) {
+            warnings.push(Warning::RenamedIdent(id, kind, name, ident));
+        }
+    };
+    // rules first, a declared leaf is also a leaf node
+    let mut types: IndexMap<&str, &'ast N<Ident>> = IndexMap::new();
+    for id in grammar
+        .rules
+        .iter()
+        .map(|r| &r.name)
+        .chain(mir.leaf_nodes.iter().copied())
+    {
+        types.entry(id.to_str()).or_insert(id);
+    }
+    for id in types.values().copied() {
+        let name = match mir.renamed(id) {
+            Some(d) => d.to_string(),
+            None => camel_case(id.to_str()),
+        };
+        if !is_std_primary(id.to_str()) {
+            check(id, "type", name, mir.type_name(id));
+        }
+    }
+    for rule in &grammar.rules {
+        if let ast::RuleKind::Enum(alts) = &rule.kind {
+            for alt in alts {
+                let name = camel_case(alt.name.to_str());
+                check(&alt.name, "variant", name, variant_name(&alt.name));
+            }
+        }
+        for body in rule.bodies() {
+            for e in body.fields() {
+                let id = e.name.as_ref().unwrap_or_else(|| e.nt());
+                check(id, "field", e.field_name(), field_name(e));
+            }
+        }
+    }
+    warnings
+}
+
+// checks that generated variant and field names are unique within a rule,
+// compared once escaped: `self` and `self_` both become `self_`
+#[derive(Debug, Clone)]
+struct NameCheck<'ast> {
+    errors: Vec<Error<'ast>>,
+    named_variants: bool,
+}
+
+impl<'ast> NameCheck<'ast> {
+    fn new() -> Self {
+        NameCheck {
+            errors: vec![],
+            named_variants: false,
+        }
+    }
+
+    fn check_variants(&mut self, rule: &'ast N<ast::Rule>, bodies: &'ast [N<ast::NamedRuleBody>]) {
+        let mut defined: IndexMap<String, &'ast N<ast::NamedRuleBody>> = IndexMap::new();
+        for b in bodies {
+            let name = variant_name(&b.name);
+            match defined.get(&name) {
+                Some(first) => self.errors.push(Error::DuplicatedVariant(rule, first, b)),
+                None => {
+                    defined.insert(name, b);
+                }
+            }
+        }
+    }
+
+    fn check_fields(&mut self, rule: &'ast N<ast::Rule>, body: &'ast ast::RuleBody) {
+        let mut defined: IndexMap<String, &'ast N<ast::RuleElement>> = IndexMap::new();
+        for e in body.fields() {
+            let name = field_name(e);
+            match defined.get(&name) {
+                Some(first) => self.errors.push(Error::DuplicatedField(rule, first, e)),
+                None => {
+                    defined.insert(name, e);
+                }
+            }
+        }
+    }
+}
+
+impl<'ast> Visitor<'ast> for NameCheck<'ast> {
+    fn visit_grammar(&mut self, n: &'ast N<Grammar>) {
+        self.named_variants = has_attr(&n.attrs, ATTR_NAMED_VARIANTS);
+        walk_grammar(self, n)
+    }
+
+    fn visit_rule(&mut self, n: &'ast N<ast::Rule>) {
+        match &n.kind {
+            ast::RuleKind::Enum(b) => {
+                self.check_variants(n, b);
+                for body in b.iter().filter_map(|b| b.body.as_ref()) {
+                    if self.named_variants || has_labels(body) {
+                        self.check_fields(n, body);
+                    }
+                }
+            }
+            ast::RuleKind::Normal(b) => self.check_fields(n, b),
+            ast::RuleKind::Leaf => {}
         }
     }
 }
@@ -97,25 +764,42 @@ impl<'ast> Visitor<'ast> for BasicCheck<'ast> {
 struct MirBuilder<'ast> {
     boxed_rules: Vec<&'ast N<Ident>>,
     rule_map: IndexMap<String, &'ast N<ast::Rule>>,
-    rules: &'ast Vec<N<ast::Rule>>,
+    rules: Vec<&'ast N<ast::Rule>>,
     leaf_nodes: IndexSet<&'ast N<ast::Ident>>,
     reserved_nodes: IndexSet<&'ast N<ast::Ident>>,
     std_primary_nodes: IndexSet<&'ast N<ast::Ident>>,
+    renames: IndexMap<String, String>,
+    global_attrs: TypeAttrs,
+    type_attrs: IndexMap<String, TypeAttrs>,
+    named_variants: bool,
+    allow: Vec<String>,
 }
 
 impl<'ast> MirBuilder<'ast> {
     fn new(grammar: &'ast N<Grammar>) -> Self {
+        // leaf declarations are not rules, their names become leaf nodes
+        // when visited
         let mut rule_map = IndexMap::new();
+        let mut rules = vec![];
         for r in &grammar.rules {
+            if let ast::RuleKind::Leaf = r.kind {
+                continue;
+            }
             let name = r.name.to_str().to_string();
             rule_map.insert(name, r);
+            rules.push(r);
         }
         MirBuilder {
             rule_map,
-            rules: &grammar.rules,
+            rules,
             leaf_nodes: IndexSet::new(),
             reserved_nodes: IndexSet::new(),
             std_primary_nodes: IndexSet::new(),
+            renames: IndexMap::new(),
+            global_attrs: TypeAttrs::default(),
+            type_attrs: IndexMap::new(),
+            named_variants: false,
+            allow: vec![],
             boxed_rules: vec![],
         }
     }
@@ -128,18 +812,50 @@ impl<'ast> MirBuilder<'ast> {
             leaf_nodes: self.leaf_nodes,
             reserved_nodes: self.reserved_nodes,
             std_primary_nodes: self.std_primary_nodes,
+            renames: self.renames,
+            global_attrs: self.global_attrs,
+            type_attrs: self.type_attrs,
+            named_variants: self.named_variants,
+            allow: self.allow,
+            warnings: vec![],
         }
     }
 }
 
 impl<'ast> Visitor<'ast> for MirBuilder<'ast> {
+    fn visit_grammar(&mut self, n: &'ast N<Grammar>) {
+        for a in &n.attrs {
+            self.global_attrs.add(a);
+        }
+        self.named_variants = has_attr(&n.attrs, ATTR_NAMED_VARIANTS);
+        for a in n.attrs.iter().filter(|a| a.to_str() == ATTR_ALLOW) {
+            for arg in &a.args {
+                if let ast::AttrValue::Path(p) = &arg.value {
+                    if !self.allow.contains(p) {
+                        self.allow.push(p.clone());
+                    }
+                }
+            }
+        }
+        walk_grammar(self, n)
+    }
+
     fn visit_rule(&mut self, n: &'ast N<ast::Rule>) {
-        for attr in &n.attrs {
-            if attr.to_str() == ATTR_BOX {
-                self.boxed_rules.push(&n.name);
-                break;
+        if n.has_attr(ATTR_BOX) {
+            self.boxed_rules.push(&n.name);
+        }
+        for a in &n.attrs {
+            if a.to_str() == ATTR_RENAME {
+                let name = n.name.to_str().to_string();
+                let ty = attr::str_arg(a).unwrap().to_string();
+                self.renames.insert(name, ty);
             }
         }
+        let mut attrs = TypeAttrs::default();
+        for a in &n.attrs {
+            attrs.add(a);
+        }
+        self.type_attrs.insert(n.name.to_str().to_string(), attrs);
         walk_rule(self, n)
     }
 
@@ -150,6 +866,13 @@ impl<'ast> Visitor<'ast> for MirBuilder<'ast> {
         }
     }
 
+    // ignore labels, they name fields and not nodes
+    fn visit_rule_element(&mut self, n: &'ast N<ast::RuleElement>) {
+        if let ast::Term::Ident(d) = &n.term {
+            self.visit_ident(d)
+        }
+    }
+
     fn visit_ident(&mut self, n: &'ast N<Ident>) {
         let name = n.to_str();
         if is_std_primary(name) {
@@ -161,3 +884,273 @@ impl<'ast> Visitor<'ast> for MirBuilder<'ast> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn parse(s: &str) -> Desugared {
+        let grammar = Parser::new(Lexer::new(s)).unwrap().parse_grammar().unwrap();
+        desugar::desugar(grammar)
+    }
+
+    fn errors(s: &str) -> Vec<Diagnostic> {
+        let grammar = parse(s);
+        let ret = match lower(&grammar) {
+            Ok(_) => vec![],
+            Err(e) => e.iter().map(|e| e.to_diagnostic()).collect(),
+        };
+        ret
+    }
+
+    fn warnings(s: &str) -> Vec<Diagnostic> {
+        let grammar = parse(s);
+        let ret = match lower(&grammar) {
+            Ok(mir) => mir.warnings.iter().map(|w| w.to_diagnostic()).collect(),
+            Err(_) => panic!("grammar is rejected"),
+        };
+        ret
+    }
+
+    fn messages(ds: &[Diagnostic]) -> Vec<&str> {
+        ds.iter().map(|d| d.message.as_str()).collect()
+    }
+
+    #[test]
+    fn test_duplicated_rule() {
+        let ds = errors("a : b ; b : c ; a : c ;");
+        assert_eq!(messages(&ds), vec!["rule `a` is defined multiple times"]);
+        assert_eq!(ds[0].notes[0].message, "first defined here");
+    }
+
+    #[test]
+    fn test_duplicated_names() {
+        let ds = errors("a : b # x | c # x ; b : c c ; c : d ;");
+        assert_eq!(
+            messages(&ds),
+            vec![
+                "variant `X` of `a` is defined multiple times",
+                "field `c` of `b` is defined multiple times",
+            ]
+        );
+        assert_eq!(
+            ds[1].notes[1].message,
+            "help: give one of them a label, e.g. `other_c=c`"
+        );
+
+        // tuple variants have no field names, labels make them struct-like
+        assert!(errors("a : c c # x | d # y ; c : d ;").is_empty());
+        assert_eq!(errors("a : c c=d # x | d # y ; c : d ;").len(), 1);
+    }
+
+    #[test]
+    fn test_unused() {
+        let ds = warnings("a : b ; b : c ; d : a ; e : statment ; statement : c ;");
+        assert_eq!(
+            messages(&ds),
+            vec![
+                "rule `d` is never used",
+                "rule `e` is never used",
+                "rule `statement` is never used",
+                "`statment` is not a rule and becomes a leaf node",
+            ]
+        );
+        assert_eq!(ds[3].notes[0].message, "help: did you mean `statement`?");
+
+        // every root keeps rules in use, leaf nodes referenced twice are
+        // intended
+        let ds = warnings("@root a : b c ; @root d : c ; b : c ;");
+        assert!(ds.is_empty());
+    }
+
+    #[test]
+    fn test_unused_leaf_decl() {
+        let ds = warnings("a : b ; @leaf b ; @leaf c ;");
+        assert_eq!(
+            messages(&ds),
+            vec!["leaf node `c` is declared but never used"]
+        );
+        assert_eq!(ds[0].notes[0].message, "declared here");
+    }
+
+    #[test]
+    fn test_undefined_with_leaf_decl() {
+        // any `@leaf` turns on strict mode
+        assert!(errors("a : b ;").is_empty());
+        let ds = errors("a : b exprr ; @leaf b ; expr : b ;");
+        assert_eq!(messages(&ds), vec!["undefined rule `exprr`"]);
+        assert_eq!(ds[0].notes[0].message, "help: did you mean `expr`?");
+    }
+
+    #[test]
+    fn test_rename() {
+        let grammar = parse("@rename(\"Expression\") expr : lit ; @rename(\"Value\") @leaf lit ;");
+        let mir = lower(&grammar).unwrap();
+        let names: Vec<_> = grammar
+            .rules
+            .iter()
+            .map(|r| mir.type_name(&r.name))
+            .collect();
+        assert_eq!(names, vec!["Expression", "Value"]);
+
+        let ds = errors("a : expr b ; @rename(\"Expr\") b : expr ; expr : c ;");
+        assert_eq!(
+            messages(&ds),
+            vec!["type `Expr` of `expr` is already generated for `b`"]
+        );
+
+        let ds = errors("@rename(\"a b\") a : b ; @rename(Foo) b : c ;");
+        assert_eq!(
+            messages(&ds),
+            vec![
+                "invalid arguments for `@rename`",
+                "invalid arguments for `@rename`",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_rust() {
+        // nothing that fails to parse as Rust reaches code generation
+        let ds = errors(
+            "@attr(\"\") a : b ; @attr(\"1\") b : c ; @rename(\"_\") c : d ; @derive(fn) d : e ;",
+        );
+        assert_eq!(
+            messages(&ds),
+            vec![
+                "invalid arguments for `@attr`",
+                "invalid arguments for `@attr`",
+                "invalid arguments for `@rename`",
+                "invalid arguments for `@derive`",
+            ]
+        );
+
+        let ds = errors("a : _ __=b ; _ : c ;");
+        assert_eq!(
+            messages(&ds),
+            vec![
+                "identifier `_` can not be used as a name",
+                "identifier `__` can not be used as a name",
+                "identifier `_` can not be used as a name",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_renamed_ident() {
+        let ds = warnings(
+            "a : self type box_ string option result ; box_ : self ; option : type ;
+            result : type ;
+            @leaf self ; @leaf type ;",
+        );
+        assert_eq!(
+            messages(&ds),
+            vec![
+                "type `Box` can not be used in Rust",
+                "type `Option` can not be used in Rust",
+                "type `Self` can not be used in Rust",
+                "field `self` can not be used in Rust",
+                "field `self` can not be used in Rust",
+            ]
+        );
+        let notes: Vec<_> = ds.iter().map(|d| d.notes[0].message.as_str()).collect();
+        assert_eq!(
+            notes,
+            vec![
+                "it is generated as `Box_`",
+                "it is generated as `Option_`",
+                "it is generated as `Self_`",
+                "it is generated as `self_`",
+                "it is generated as `self_`",
+            ]
+        );
+
+        // names are unique once escaped
+        let ds = errors("a : self self_ ;");
+        assert_eq!(
+            messages(&ds),
+            vec!["field `self_` of `a` is defined multiple times"]
+        );
+        let ds = errors("a : box box_ ;");
+        assert_eq!(
+            messages(&ds),
+            vec!["type `Box_` of `box_` is already generated for `box`"]
+        );
+    }
+
+    #[test]
+    fn test_sql() {
+        // prelude names other than the ones generated code uses are fine
+        let ds = warnings(include_str!("../grammars/sql.yp"));
+        assert!(ds.is_empty(), "{:?}", messages(&ds));
+    }
+
+    #[test]
+    fn test_type_attrs() {
+        let s = "@!derive(PartialEq) @!attr(\"non_exhaustive\")
+            @derive(Eq, PartialEq) @attr(\"serde(untagged)\") a : b ;
+            @derive(Hash) @leaf b ;";
+        let grammar = parse(s);
+        let mir = lower(&grammar).unwrap();
+        let a = mir.type_attrs(&grammar.rules[0].name);
+        assert_eq!(a.derives, vec!["PartialEq", "Eq"]);
+        assert_eq!(a.attrs, vec!["non_exhaustive", "serde(untagged)"]);
+        let b = mir.type_attrs(&grammar.rules[1].name);
+        assert_eq!(b.derives, vec!["PartialEq", "Hash"]);
+        assert_eq!(b.attrs, vec!["non_exhaustive"]);
+
+        let ds = errors("@!root @derive a : b ; @attr(Foo) b : c ; @derive(a = b) c : d ;");
+        assert_eq!(
+            messages(&ds),
+            vec![
+                "attribute `@root` can not apply to the whole grammar",
+                "invalid arguments for `@derive`",
+                "invalid arguments for `@attr`",
+                "invalid arguments for `@derive`",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_group_labels() {
+        // labels are scoped to their rule
+        let s = "a : x=(b c) y=(d | e)? ; f : x=(b | c)* ; b : d ; c : d ;";
+        let grammar = parse(s);
+        let mir = lower(&grammar).unwrap();
+        let names: Vec<_> = mir.rules.iter().map(|r| r.name.to_str()).collect();
+        assert_eq!(names, vec!["a", "a_x", "a_y", "f", "f_x", "b", "c"]);
+    }
+
+    #[test]
+    fn test_duplicated_label() {
+        let ds = errors("a : x=(b c) # p | x=(c) # q ; b : c=(d | e) ; c : x=(d)* ;");
+        assert_eq!(messages(&ds), vec!["duplicate label `x`"]);
+        assert_eq!(ds[0].notes[0].message, "first used here");
+        let label = "a : x=(b c) # p | ".len();
+        assert_eq!(ds[0].span.map(|s| s.start), Some(label));
+    }
+
+    #[test]
+    fn test_labels_are_no_nodes() {
+        let grammar = parse("a : left=b right=b super_=c? ; @leaf b ; @leaf c ;");
+        let mir = lower(&grammar).unwrap();
+        let leaf_nodes: Vec<_> = mir.leaf_nodes.iter().map(|n| n.to_str()).collect();
+        assert_eq!(leaf_nodes, vec!["b", "c"]);
+        // nor are they renamed types
+        assert!(mir.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_auto_box_source_rules() {
+        let grammar = parse("a : b x=(a b)? ; b : c ;");
+        let options = Options {
+            auto_box: true,
+            ..Options::default()
+        };
+        let mir = lower_with(&grammar, &options).unwrap();
+        let boxed: Vec<_> = mir.boxed_rules.iter().map(|r| r.to_str()).collect();
+        assert_eq!(boxed, vec!["a"]);
+    }
+}
@@ -0,0 +1,141 @@
+use reacto::ast::N;
+
+use crate::ast::{Attr, AttrArg, AttrValue};
+use crate::util::{is_ident, is_underscore};
+
+pub static ATTR_BOX: &str = "box";
+pub static ATTR_ROOT: &str = "root";
+pub static ATTR_LEAF: &str = "leaf";
+pub static ATTR_RENAME: &str = "rename";
+pub static ATTR_DERIVE: &str = "derive";
+pub static ATTR_ATTR: &str = "attr";
+pub static ATTR_NAMED_VARIANTS: &str = "named_variants";
+pub static ATTR_ALLOW: &str = "allow";
+
+/// Every attribute a grammar may use. New attributes only need an entry here
+/// to be accepted and have their arguments checked.
+pub static ATTRS: &[AttrSpec] = &[
+    AttrSpec {
+        name: ATTR_BOX,
+        args: ArgSpec::None,
+        target: Target::Rule,
+        usage: "@box",
+    },
+    AttrSpec {
+        name: ATTR_ROOT,
+        args: ArgSpec::None,
+        target: Target::Rule,
+        usage: "@root",
+    },
+    AttrSpec {
+        name: ATTR_LEAF,
+        args: ArgSpec::None,
+        target: Target::Rule,
+        usage: "@leaf",
+    },
+    AttrSpec {
+        name: ATTR_RENAME,
+        args: ArgSpec::Ident,
+        target: Target::Rule,
+        usage: "@rename(\"TypeName\")",
+    },
+    AttrSpec {
+        name: ATTR_DERIVE,
+        args: ArgSpec::Paths,
+        target: Target::Any,
+        usage: "@derive(PartialEq, serde::Serialize)",
+    },
+    AttrSpec {
+        name: ATTR_ATTR,
+        args: ArgSpec::Meta,
+        target: Target::Any,
+        usage: "@attr(\"non_exhaustive\")",
+    },
+    AttrSpec {
+        name: ATTR_NAMED_VARIANTS,
+        args: ArgSpec::None,
+        target: Target::Grammar,
+        usage: "@!named_variants",
+    },
+    AttrSpec {
+        name: ATTR_ALLOW,
+        args: ArgSpec::Paths,
+        target: Target::Grammar,
+        usage: "@!allow(clippy::all, dead_code)",
+    },
+];
+
+#[derive(Debug, Clone, Copy)]
+pub struct AttrSpec {
+    pub name: &'static str,
+    pub args: ArgSpec,
+    pub target: Target,
+    /// Example shown when the arguments are invalid.
+    pub usage: &'static str,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ArgSpec {
+    /// No parentheses.
+    None,
+    /// One or more paths, e.g. `(PartialEq, serde::Serialize)`.
+    Paths,
+    /// A single string naming a Rust item, e.g. `("Foo")`.
+    Ident,
+    /// A single string holding the inside of a Rust attribute, e.g.
+    /// `("serde(tag = \"type\")")`.
+    Meta,
+}
+
+/// Where an attribute may appear.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Target {
+    /// `@name` before a rule.
+    Rule,
+    /// `@!name` at the top of the grammar.
+    Grammar,
+    /// `@name` before a rule or `@!name` at the top of the grammar.
+    Any,
+}
+
+pub fn lookup(name: &str) -> Option<&'static AttrSpec> {
+    ATTRS.iter().find(|s| s.name == name)
+}
+
+pub fn names() -> impl Iterator<Item = &'static str> {
+    ATTRS.iter().map(|s| s.name)
+}
+
+impl ArgSpec {
+    pub fn accepts(&self, args: &[N<AttrArg>]) -> bool {
+        // paths are made of identifiers, keywords are no path
+        // no attribute takes `key = value` arguments yet
+        let is_path = |a: &N<AttrArg>| match &a.value {
+            AttrValue::Path(p) => a.key.is_none() && syn::parse_str::<syn::Path>(p).is_ok(),
+            AttrValue::Str(_) => false,
+        };
+        let str_arg = match args {
+            [a] if a.key.is_none() => match &a.value {
+                AttrValue::Str(s) => Some(s),
+                AttrValue::Path(_) => None,
+            },
+            _ => None,
+        };
+        match self {
+            ArgSpec::None => args.is_empty(),
+            ArgSpec::Paths => !args.is_empty() && args.iter().all(is_path),
+            ArgSpec::Ident => matches!(str_arg, Some(s) if is_ident(s) && !is_underscore(s)),
+            // code generation puts it inside `#[..]`
+            ArgSpec::Meta => matches!(str_arg, Some(s) if syn::parse_str::<syn::Meta>(s).is_ok()),
+        }
+    }
+}
+
+/// The string argument of an `ArgSpec::Ident` or `ArgSpec::Meta`
+/// attribute.
+pub fn str_arg(attr: &Attr) -> Option<&str> {
+    match attr.args.first().map(|a| &a.value) {
+        Some(AttrValue::Str(s)) => Some(s),
+        _ => None,
+    }
+}
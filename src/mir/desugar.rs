@@ -0,0 +1,196 @@
+use std::ops::Deref;
+
+use indexmap::map::IndexMap;
+use indexmap::set::IndexSet;
+use reacto::ast::N;
+
+use crate::ast::{Grammar, Group, Ident, Rule, RuleBody, RuleElement, RuleKind, Term};
+
+/// A grammar without groups, only `desugar` makes one so `mir::lower` can
+/// rely on it.
+#[derive(Debug, Clone)]
+pub struct Desugared {
+    grammar: N<Grammar>,
+    synthesized: IndexSet<String>,
+    duplicated_labels: Vec<(N<Ident>, N<Ident>)>,
+}
+
+impl Desugared {
+    /// Whether rule `name` was made from a group rather than written in the
+    /// grammar.
+    pub fn is_synthesized(&self, name: &str) -> bool {
+        self.synthesized.contains(name)
+    }
+
+    /// Group labels used more than once in a rule, (first, duplicate).
+    pub fn duplicated_labels(&self) -> &[(N<Ident>, N<Ident>)] {
+        &self.duplicated_labels
+    }
+}
+
+impl Deref for Desugared {
+    type Target = N<Grammar>;
+
+    fn deref(&self) -> &N<Grammar> {
+        &self.grammar
+    }
+}
+
+/// Replace every group with a reference to a synthesized rule, so the rest of
+/// the pipeline only sees plain elements.
+///
+/// A group is named `{rule}_{label}` after its label, `sep=(comma item)*` in
+/// rule `list` becomes rule `list_sep`, otherwise `{rule}_group{n}` with `n`
+/// counting the groups of the enclosing rule in source order. Synthesized
+/// rules follow the rule they come from. A label used again in the same rule
+/// is recorded in `Desugared::duplicated_labels`, its group is named as if
+/// it had none.
+pub fn desugar(grammar: N<Grammar>) -> Desugared {
+    let mut ret = grammar.clone();
+    ret.data.rules = vec![];
+    let mut synthesized = IndexSet::new();
+    let mut duplicated_labels = vec![];
+    for rule in &grammar.rules {
+        let mut d = Desugar {
+            rule,
+            count: 0,
+            rules: vec![],
+            labels: IndexMap::new(),
+            duplicated_labels: vec![],
+        };
+        let mut rule = rule.clone();
+        rule.data.kind = d.kind(&rule.kind);
+        ret.data.rules.push(rule);
+        synthesized.extend(d.rules.iter().map(|r| r.name.to_str().to_string()));
+        ret.data.rules.extend(d.rules);
+        duplicated_labels.extend(d.duplicated_labels);
+    }
+    Desugared {
+        grammar: ret,
+        synthesized,
+        duplicated_labels,
+    }
+}
+
+struct Desugar<'a> {
+    rule: &'a N<Rule>,
+    count: usize,
+    rules: Vec<N<Rule>>,
+    /// Group labels of `rule` seen so far.
+    labels: IndexMap<String, N<Ident>>,
+    duplicated_labels: Vec<(N<Ident>, N<Ident>)>,
+}
+
+impl<'a> Desugar<'a> {
+    fn kind(&mut self, kind: &RuleKind) -> RuleKind {
+        match kind {
+            RuleKind::Enum(alts) => {
+                let mut ret = alts.clone();
+                for alt in &mut ret {
+                    alt.data.body = alt.data.body.take().map(|b| self.body(&b));
+                }
+                RuleKind::Enum(ret)
+            }
+            RuleKind::Normal(b) => RuleKind::Normal(self.body(b)),
+            RuleKind::Leaf => RuleKind::Leaf,
+        }
+    }
+
+    fn body(&mut self, body: &N<RuleBody>) -> N<RuleBody> {
+        let mut ret = body.clone();
+        ret.data.body = body.body.iter().map(|e| self.element(e)).collect();
+        ret
+    }
+
+    fn element(&mut self, ele: &N<RuleElement>) -> N<RuleElement> {
+        let group = match &ele.term {
+            Term::Group(d) => d,
+            Term::Ident(_) | Term::Literal(_) => return ele.clone(),
+        };
+        self.count += 1;
+        let name = match &ele.name {
+            Some(d) => match self.labels.get(d.to_str()) {
+                Some(first) => {
+                    self.duplicated_labels.push((first.clone(), d.clone()));
+                    self.group_name(group)
+                }
+                None => {
+                    self.labels.insert(d.to_str().to_string(), d.clone());
+                    self.label_name(d)
+                }
+            },
+            None => self.group_name(group),
+        };
+        // keep outer groups before the ones nested in them
+        let at = self.rules.len();
+        let kind = match group.alts.as_slice() {
+            [alt] => RuleKind::Normal(self.body(alt.body.as_ref().unwrap())),
+            alts => self.kind(&RuleKind::Enum(alts.to_vec())),
+        };
+        let mut rule = self.rule.clone();
+        rule.span = group.span;
+        rule.data = Rule {
+            docs: vec![],
+            attrs: vec![],
+            name: name.clone(),
+            kind,
+        };
+        self.rules.insert(at, rule);
+
+        let mut ret = ele.clone();
+        ret.data.term = Term::Ident(name);
+        ret
+    }
+
+    /// Labels are only unique within a rule, the rule name is prepended. The
+    /// span stays the one of the label, so collisions are reported there.
+    fn label_name(&self, label: &N<Ident>) -> N<Ident> {
+        let mut ret = label.clone();
+        ret.data = Ident {
+            name: format!("{}_{}", self.rule.name.to_str(), label.to_str()),
+        };
+        ret
+    }
+
+    fn group_name(&self, group: &N<Group>) -> N<Ident> {
+        let mut ret = self.rule.name.clone();
+        ret.span = group.span;
+        ret.data = Ident {
+            name: format!("{}_group{}", self.rule.name.to_str(), self.count),
+        };
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_desugar() {
+        let s = "a : x=(b | c d # cd) (e (f g)?)* ;";
+        let grammar = Parser::new(Lexer::new(s)).unwrap().parse_grammar().unwrap();
+        let grammar = desugar(grammar);
+        let names: Vec<_> = grammar.rules.iter().map(|r| r.name.to_str()).collect();
+        assert_eq!(names, vec!["a", "a_x", "a_group2", "a_group3"]);
+        assert!(!grammar.is_synthesized("a"));
+        assert!(grammar.is_synthesized("a_x"));
+
+        let fields: Vec<_> = grammar.rules[0].bodies()[0]
+            .body
+            .iter()
+            .map(|e| e.field_name())
+            .collect();
+        assert_eq!(fields, vec!["x", "a_group2s"]);
+        match &grammar.rules[1].kind {
+            RuleKind::Enum(alts) => {
+                let variants: Vec<_> = alts.iter().map(|a| a.name.to_str()).collect();
+                assert_eq!(variants, vec!["b", "cd"]);
+            }
+            _ => panic!("a group with alternatives is an enum"),
+        }
+        assert!(matches!(grammar.rules[2].kind, RuleKind::Normal(_)));
+    }
+}
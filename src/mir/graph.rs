@@ -0,0 +1,330 @@
+use std::cmp::Reverse;
+
+use indexmap::map::IndexMap;
+use indexmap::set::IndexSet;
+use itertools::Itertools;
+use reacto::ast::N;
+
+use crate::ast;
+use crate::ast::Grammar;
+
+/// Cycles with more candidate rules than this are broken greedily, searching
+/// a smallest set of rules to box is exponential.
+const EXACT_SEARCH_LIMIT: usize = 8;
+
+/// Dependency graph between rules, one edge per element referring to another
+/// rule. Leaf nodes, reserved and std primary types are not part of it.
+#[derive(Debug, Clone)]
+pub struct RuleGraph<'ast> {
+    pub rules: IndexMap<&'ast str, &'ast N<ast::Rule>>,
+    pub edges: IndexMap<&'ast str, Vec<Edge<'ast>>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Edge<'ast> {
+    pub to: &'ast str,
+    pub element: &'ast N<ast::RuleElement>,
+}
+
+impl<'ast> Edge<'ast> {
+    /// Elements collected into a `Vec` live on the heap, so they never make
+    /// the containing type infinitely sized.
+    pub fn is_indirect(&self) -> bool {
+        self.element.has_many()
+    }
+}
+
+impl<'ast> RuleGraph<'ast> {
+    pub fn new(grammar: &'ast N<Grammar>) -> Self {
+        let mut rules = IndexMap::new();
+        for r in &grammar.rules {
+            rules.entry(r.name.to_str()).or_insert(r);
+        }
+
+        let mut edges = IndexMap::new();
+        for (name, rule) in &rules {
+            let mut out = vec![];
+            for body in rule.bodies() {
+                for e in body.fields() {
+                    let to = e.nt().to_str();
+                    if rules.contains_key(to) {
+                        out.push(Edge { to, element: e });
+                    }
+                }
+            }
+            edges.insert(*name, out);
+        }
+        RuleGraph { rules, edges }
+    }
+
+    /// Rules reachable from `roots` following every edge.
+    pub fn reachable(&self, roots: &[&'ast str]) -> IndexSet<&'ast str> {
+        let mut ret = IndexSet::new();
+        let mut stack = roots.to_vec();
+        while let Some(r) = stack.pop() {
+            if ret.insert(r) {
+                stack.extend(self.edges[r].iter().map(|e| e.to));
+            }
+        }
+        ret
+    }
+
+    /// Groups of rules that contain each other by value when the rules in
+    /// `boxed` are put behind a `Box`, each of them makes an infinitely sized
+    /// type.
+    pub fn infinite_cycles(&self, boxed: &IndexSet<&'ast str>) -> Vec<Vec<&'ast str>> {
+        self.cycles(|e| !e.is_indirect() && !boxed.contains(e.to))
+    }
+
+    /// A small set of rules for which `is_candidate` holds to box in addition
+    /// to `boxed` so no infinite cycle is left. A smallest one is searched per
+    /// cycle of at most `EXACT_SEARCH_LIMIT` candidates, larger cycles are
+    /// broken greedily.
+    pub fn minimal_boxing<F>(&self, boxed: &IndexSet<&'ast str>, is_candidate: F) -> Vec<&'ast str>
+    where
+        F: Fn(&str) -> bool,
+    {
+        let mut ret = vec![];
+        for cycle in self.infinite_cycles(boxed) {
+            let candidates: Vec<_> = cycle.iter().copied().filter(|r| is_candidate(r)).collect();
+            let smallest = if candidates.len() <= EXACT_SEARCH_LIMIT {
+                self.smallest_boxing(boxed, &cycle, &candidates)
+            } else {
+                None
+            };
+            ret.extend(smallest.unwrap_or_else(|| self.greedy_boxing(boxed, &cycle, &candidates)));
+        }
+        ret
+    }
+
+    /// Tries every subset of `candidates`, smallest first.
+    fn smallest_boxing(
+        &self,
+        boxed: &IndexSet<&'ast str>,
+        cycle: &[&'ast str],
+        candidates: &[&'ast str],
+    ) -> Option<Vec<&'ast str>> {
+        (1..=candidates.len()).find_map(|k| {
+            candidates.iter().copied().combinations(k).find(|extra| {
+                let mut boxed = boxed.clone();
+                boxed.extend(extra.iter().copied());
+                self.remaining_cycles(&boxed, cycle).is_empty()
+            })
+        })
+    }
+
+    /// Boxes the candidate the most rules of its cycle contain by value until
+    /// the cycle is broken, or no candidate is left.
+    fn greedy_boxing(
+        &self,
+        boxed: &IndexSet<&'ast str>,
+        cycle: &[&'ast str],
+        candidates: &[&'ast str],
+    ) -> Vec<&'ast str> {
+        let mut boxed = boxed.clone();
+        let mut ret = vec![];
+        loop {
+            let remain = self.remaining_cycles(&boxed, cycle);
+            let best = remain
+                .iter()
+                .flat_map(|c| c.iter().map(move |r| (c, *r)))
+                .filter(|(_, r)| candidates.contains(r))
+                .max_by_key(|(c, r)| {
+                    let refs = c
+                        .iter()
+                        .flat_map(|from| &self.edges[from])
+                        .filter(|e| e.to == *r && !e.is_indirect())
+                        .count();
+                    // the first defined rule on ties
+                    (refs, Reverse(self.rules.get_index_of(r)))
+                });
+            match best {
+                Some((_, r)) => {
+                    boxed.insert(r);
+                    ret.push(r);
+                }
+                None => return ret,
+            }
+        }
+    }
+
+    /// Infinite cycles left among the rules of `cycle`.
+    fn remaining_cycles(
+        &self,
+        boxed: &IndexSet<&'ast str>,
+        cycle: &[&'ast str],
+    ) -> Vec<Vec<&'ast str>> {
+        let mut ret = self.infinite_cycles(boxed);
+        ret.retain(|c| c.iter().any(|r| cycle.contains(r)));
+        ret
+    }
+
+    /// The graph in Graphviz dot format, rules for which `is_boxed` holds are
+    /// drawn as boxes and edges through a `Vec` are dashed.
+    pub fn to_dot<F>(&self, is_boxed: F) -> String
+    where
+        F: Fn(&str) -> bool,
+    {
+        let mut ret = String::from("digraph grammar {\n");
+        for name in self.rules.keys() {
+            let shape = if is_boxed(name) { " [shape=box]" } else { "" };
+            ret.push_str(&format!("    {:?}{};\n", name, shape));
+        }
+        for (from, edges) in &self.edges {
+            for e in edges {
+                let style = if e.is_indirect() {
+                    " [style=dashed]"
+                } else {
+                    ""
+                };
+                ret.push_str(&format!("    {:?} -> {:?}{};\n", from, e.to, style));
+            }
+        }
+        ret.push_str("}\n");
+        ret
+    }
+
+    /// Strongly connected components of the graph restricted to `follow`
+    /// edges that contain a cycle, found with Tarjan's algorithm.
+    fn cycles<F>(&self, follow: F) -> Vec<Vec<&'ast str>>
+    where
+        F: Fn(&Edge<'ast>) -> bool,
+    {
+        let mut tarjan = Tarjan {
+            graph: self,
+            follow: &follow,
+            index: IndexMap::new(),
+            low: IndexMap::new(),
+            stack: vec![],
+            on_stack: IndexSet::new(),
+            sccs: vec![],
+        };
+        for name in self.rules.keys() {
+            if !tarjan.index.contains_key(name) {
+                tarjan.connect(name);
+            }
+        }
+
+        let mut ret = vec![];
+        for scc in tarjan.sccs {
+            let is_cycle = scc.len() > 1
+                || self.edges[scc[0]]
+                    .iter()
+                    .any(|e| follow(e) && e.to == scc[0]);
+            if is_cycle {
+                ret.push(scc);
+            }
+        }
+        // report in definition order
+        for scc in &mut ret {
+            scc.sort_by_key(|r| self.rules.get_index_of(r));
+        }
+        ret.sort_by_key(|scc| self.rules.get_index_of(scc[0]));
+        ret
+    }
+}
+
+struct Tarjan<'a, 'ast, F> {
+    graph: &'a RuleGraph<'ast>,
+    follow: &'a F,
+    index: IndexMap<&'ast str, usize>,
+    low: IndexMap<&'ast str, usize>,
+    stack: Vec<&'ast str>,
+    on_stack: IndexSet<&'ast str>,
+    sccs: Vec<Vec<&'ast str>>,
+}
+
+impl<'a, 'ast, F> Tarjan<'a, 'ast, F>
+where
+    F: Fn(&Edge<'ast>) -> bool,
+{
+    fn connect(&mut self, v: &'ast str) {
+        let idx = self.index.len();
+        self.index.insert(v, idx);
+        self.low.insert(v, idx);
+        self.stack.push(v);
+        self.on_stack.insert(v);
+
+        let graph = self.graph;
+        for e in &graph.edges[v] {
+            if !(self.follow)(e) {
+                continue;
+            }
+            if !self.index.contains_key(e.to) {
+                self.connect(e.to);
+                let low = self.low[v].min(self.low[e.to]);
+                self.low.insert(v, low);
+            } else if self.on_stack.contains(e.to) {
+                let low = self.low[v].min(self.index[e.to]);
+                self.low.insert(v, low);
+            }
+        }
+
+        if self.low[v] == self.index[v] {
+            let mut scc = vec![];
+            while let Some(w) = self.stack.pop() {
+                self.on_stack.remove(w);
+                scc.push(w);
+                if w == v {
+                    break;
+                }
+            }
+            self.sccs.push(scc);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_infinite_cycles() {
+        let s = "a : b ; b : a c* ; c : a? ;";
+        let grammar = Parser::new(Lexer::new(s)).unwrap().parse_grammar().unwrap();
+        let graph = RuleGraph::new(&grammar);
+
+        let mut boxed = IndexSet::new();
+        assert_eq!(graph.infinite_cycles(&boxed), vec![vec!["a", "b"]]);
+        assert_eq!(graph.minimal_boxing(&boxed, |_| true), vec!["a"]);
+        assert_eq!(graph.minimal_boxing(&boxed, |r| r != "a"), vec!["b"]);
+        boxed.insert("a");
+        assert!(graph.infinite_cycles(&boxed).is_empty());
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let s = "a : b* ; b : a ;";
+        let grammar = Parser::new(Lexer::new(s)).unwrap().parse_grammar().unwrap();
+        let graph = RuleGraph::new(&grammar);
+        let expected = r#"digraph grammar {
+    "a";
+    "b" [shape=box];
+    "a" -> "b" [style=dashed];
+    "b" -> "a";
+}
+"#;
+        assert_eq!(graph.to_dot(|r| r == "b"), expected);
+    }
+
+    #[test]
+    fn test_greedy_boxing() {
+        // a ring of 12 rules, each also containing the one after next
+        let s: String = (0..12)
+            .map(|i| format!("r{} : r{} r{} ; ", i, (i + 1) % 12, (i + 2) % 12))
+            .collect();
+        let grammar = Parser::new(Lexer::new(&s))
+            .unwrap()
+            .parse_grammar()
+            .unwrap();
+        let graph = RuleGraph::new(&grammar);
+
+        let mut boxed = IndexSet::new();
+        let extra = graph.minimal_boxing(&boxed, |_| true);
+        assert_eq!(extra[0], "r0");
+        boxed.extend(extra);
+        assert!(graph.infinite_cycles(&boxed).is_empty());
+    }
+}
@@ -1,5 +1,6 @@
 use std::error::Error;
 use std::fmt;
+use std::rc::Rc;
 
 use iterable::Iterable;
 use reacto::ast::N;
@@ -9,26 +10,66 @@ use reacto::span::{Span, S};
 use reacto::*;
 
 use crate::ast::{
-    Attr, Grammar, Ident, NamedRuleBody, Quantifier, Rule, RuleBody, RuleElement, RuleKind,
+    Attr, AttrArg, AttrValue, Comment, DocComment, Grammar, Group, Ident, Literal, NamedRuleBody,
+    Quantifier, Rule, RuleBody, RuleElement, RuleKind, Term,
 };
+use crate::diagnostic::Diagnostic;
 use crate::lexer::{LexError, Lexer};
-use crate::token::Token;
+use crate::location::Location;
+use crate::token::{Token, Whitespace};
+use crate::util::unescape;
 
 #[derive(Debug)]
 pub struct ParseError {
     span: Option<Span>,
+    location: Option<Box<Location>>,
     kind: ParseErrorKind,
 }
 
+impl ParseError {
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
+
+    pub fn kind(&self) -> &ParseErrorKind {
+        &self.kind
+    }
+
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        use ParseErrorKind::*;
+        match &self.kind {
+            LexError(e) => e.to_diagnostic(),
+            DuplicatedSepOrParseError(sep, e) => e
+                .to_diagnostic()
+                .with_note(format!("there may be a duplicated {}", sep)),
+            UnexpectedToken(..) | UnexpectedTokenMulti(..) | DanglingDocComment(_) | Eof => {
+                Diagnostic::error(self.kind.to_string()).with_span(self.span)
+            }
+        }
+    }
+}
+
 impl fmt::Display for ParseError {
-    fn fmt(&self, _f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        todo!()
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the lexer error knows where it failed, and what went wrong there
+        if let ParseErrorKind::LexError(e) = &self.kind {
+            return write!(f, "{}: {}", self.kind, e);
+        }
+        match &self.location {
+            Some(loc) => write!(f, "{}: {}\n{}", loc, self.kind, loc.snippet()),
+            None => write!(f, "{}", self.kind),
+        }
     }
 }
 
 impl Error for ParseError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
-        todo!()
+        use ParseErrorKind::*;
+        match &self.kind {
+            LexError(e) => Some(e),
+            DuplicatedSepOrParseError(_, e) => Some(&**e),
+            UnexpectedToken(..) | UnexpectedTokenMulti(..) | DanglingDocComment(_) | Eof => None,
+        }
     }
 }
 
@@ -41,144 +82,377 @@ pub enum ParseErrorKind {
     UnexpectedTokenMulti(Vec<Token>, Option<S<Token>>),
     // (sep, error)
     DuplicatedSepOrParseError(Token, Box<ParseError>),
+    // what the doc comment should have been followed by
+    DanglingDocComment(&'static str),
     Eof,
 }
 
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ParseErrorKind::*;
+        match self {
+            LexError(_) => write!(f, "failed to tokenize grammar"),
+            UnexpectedToken(expected, found) => {
+                write!(f, "expected {}, found {}", expected, FoundToken(found))
+            }
+            UnexpectedTokenMulti(expected, found) => {
+                let expected: Vec<_> = expected.iter().map(|t| t.to_string()).collect();
+                let expected = expected.join(", ");
+                write!(
+                    f,
+                    "expected one of {}, found {}",
+                    expected,
+                    FoundToken(found)
+                )
+            }
+            DuplicatedSepOrParseError(sep, _) => {
+                write!(f, "duplicated {} or invalid item after it", sep)
+            }
+            DanglingDocComment(what) => write!(f, "doc comment is not followed by {}", what),
+            Eof => write!(f, "unexpected end of file"),
+        }
+    }
+}
+
+struct FoundToken<'a>(&'a Option<S<Token>>);
+
+impl fmt::Display for FoundToken<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(t) => write!(f, "{}", t.tok),
+            None => write!(f, "end of file"),
+        }
+    }
+}
+
 pub type Result<T> = std::result::Result<T, ParseError>;
 
 #[derive(Clone, Debug)]
 pub struct Parser {
     ctx: ParseCtx<Token>,
+    source: Rc<str>,
+    comments: Vec<Comment>,
 }
 
 fn remove_junk(tokens: &[S<Token>]) -> Vec<S<Token>> {
     let mut ret = vec![];
     for t in tokens {
         match t.tok {
-            Token::Whitespace(_) => {}
+            Token::Whitespace(_) | Token::Comment(_) => {}
             _ => ret.push(*t),
         }
     }
     ret
 }
 
+fn collect_comments(source: &str, tokens: &[S<Token>]) -> Vec<Comment> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut ret = vec![];
+    for (i, t) in tokens.iter().enumerate() {
+        if let Token::Comment(_) = t.tok {
+            let text = chars[t.span.start..t.span.end].iter().collect();
+            let newlines = tokens[i + 1..]
+                .iter()
+                .take_while(|t| matches!(t.tok, Token::Whitespace(_)))
+                .filter(|t| t.tok == Token::Whitespace(Whitespace::Newline))
+                .count();
+            ret.push(Comment {
+                span: t.span,
+                text,
+                blank_after: newlines > 1,
+            });
+        }
+    }
+    ret
+}
+
 impl Parser {
     pub fn new(mut lexer: Lexer) -> Result<Self> {
         let tokens = match lexer.tokens() {
             Ok(d) => d,
             Err(e) => {
-                let span = None;
+                let span = e.span();
+                let location = e.location().cloned().map(Box::new);
                 let kind = ParseErrorKind::LexError(e);
-                return Err(ParseError { span, kind });
+                return Err(ParseError {
+                    span,
+                    location,
+                    kind,
+                });
             }
         };
         let chars = lexer.chars();
+        let source = lexer.source().clone();
+        let comments = collect_comments(&source, &tokens);
         let tokens = remove_junk(&tokens);
         let ctx = ParseCtx::new(chars.clone(), tokens);
-        Ok(Parser { ctx })
+        Ok(Parser {
+            ctx,
+            source,
+            comments,
+        })
+    }
+
+    fn make_error(&self, span: Option<Span>, kind: ParseErrorKind) -> ParseError {
+        let location = span.map(|s| Box::new(Location::new(&self.source, s)));
+        ParseError {
+            span,
+            location,
+            kind,
+        }
     }
 }
 
 impl Parser {
     pub fn parse_grammar(&mut self) -> Result<N<Grammar>> {
         self.parse_n(|parser| {
+            let attrs = parse_many_l1!(parser, parse_inner_attr, Token::InnerAttr);
             let mut rules = vec![];
             while !parser.eof() {
                 let rule = parser.parse_rule()?;
                 rules.push(rule);
             }
-            Ok(Grammar { rules })
+            let comments = parser.comments.clone();
+            Ok(Grammar {
+                attrs,
+                rules,
+                comments,
+            })
         })
     }
 
     pub fn parse_rule(&mut self) -> Result<N<Rule>> {
         fn parse_alts(
             parser: &mut Parser,
+            docs: Vec<N<DocComment>>,
             name: N<Ident>,
             body: Option<N<RuleBody>>,
         ) -> Result<RuleKind> {
-            let head = parser.make_node(NamedRuleBody { name, body });
+            let head = parser.make_node(NamedRuleBody { docs, name, body });
             let mut alts = vec![head];
-            expect_one_of! { parser,
-                Token::Alt => {
-                    let rest = parse_some!(parser, parse_named_rule_body, Token::Alt);
-                    parser.expect(Token::Semicolon)?;
-                    alts.extend(rest);
-                },
-                Token::Semicolon => {
-                    // do nothing
-                }
-            };
+            loop {
+                // docs before and after `|` belong to the alternative after it
+                let docs = parse_many_l1!(parser, parse_doc_comment, Token::DocComment);
+                expect_one_of! { parser,
+                    Token::Alt => {
+                        let alt = parser.parse_named_rule_body(docs)?;
+                        alts.push(alt);
+                    },
+                    Token::Semicolon => {
+                        break;
+                    }
+                };
+            }
             Ok(RuleKind::Enum(alts))
         }
 
+        fn parse_body(
+            parser: &mut Parser,
+            docs: &mut Vec<N<DocComment>>,
+            mut head_docs: Vec<N<DocComment>>,
+            alt_docs: Vec<N<DocComment>>,
+        ) -> Result<RuleKind> {
+            let mut body = parser.parse_rule_body()?;
+            if parser.advance_cmp(Token::NumSign) {
+                head_docs.extend(alt_docs);
+                let name = parser.parse_ident()?;
+                parse_alts(parser, head_docs, name, Some(body))
+            } else {
+                parser.expect(Token::Semicolon)?;
+                // a struct has no alternative to document, docs after `:`
+                // stay with the first element
+                docs.extend(head_docs);
+                let first = &mut body.data.body[0].data.docs;
+                first.splice(0..0, alt_docs);
+                Ok(RuleKind::Normal(body))
+            }
+        }
+
         self.parse_n(|parser| {
+            let mut docs = parse_many_l1!(parser, parse_doc_comment, Token::DocComment);
             let attrs = parse_many_l1!(parser, parse_attr, Token::Attr);
+            docs.extend(parse_many_l1!(parser, parse_doc_comment, Token::DocComment));
+            parser.expect_documented(&docs, &[Token::Ident], "a rule")?;
             let name = parser.parse_ident()?;
+            // docs between the name and `:` belong to the first alternative
+            let mut head_docs = parse_many_l1!(parser, parse_doc_comment, Token::DocComment);
+            if parser.advance_cmp(Token::Semicolon) {
+                docs.extend(head_docs);
+                let kind = RuleKind::Leaf;
+                return Ok(Rule {
+                    docs,
+                    attrs,
+                    name,
+                    kind,
+                });
+            }
             parser.expect(Token::Colon)?;
+            // and so do the ones after it, like the ones after `|`
+            let alt_docs = parse_many_l1!(parser, parse_doc_comment, Token::DocComment);
 
             let kind = sat_one_of! { parser,
                 Token::NumSign => {
                     parser.advance();
+                    head_docs.extend(alt_docs);
                     let name = parser.parse_ident()?;
-                    parse_alts(parser, name, None)?
+                    parse_alts(parser, head_docs, name, None)?
                 },
                 Token::Ident => {
-                    let body = parser.parse_rule_body()?;
-                    if parser.advance_cmp(Token::NumSign) {
-                        let name = parser.parse_ident()?;
-                        parse_alts(parser, name, Some(body))?
-                    } else {
-                        parser.expect(Token::Semicolon)?;
-                        RuleKind::Normal(body)
-                    }
+                    parse_body(parser, &mut docs, head_docs, alt_docs)?
+                },
+                Token::LParen => {
+                    parse_body(parser, &mut docs, head_docs, alt_docs)?
+                },
+                Token::LitString => {
+                    parse_body(parser, &mut docs, head_docs, alt_docs)?
                 }
             };
-            Ok(Rule { attrs, name, kind })
+            Ok(Rule {
+                docs,
+                attrs,
+                name,
+                kind,
+            })
         })
     }
 
-    pub fn parse_named_rule_body(&mut self) -> Result<N<NamedRuleBody>> {
+    /// An alternative after `|`, `docs` are the ones before `|`, the ones
+    /// right after it are added to them.
+    pub fn parse_named_rule_body(&mut self, docs: Vec<N<DocComment>>) -> Result<N<NamedRuleBody>> {
         self.parse_n(|parser| {
-            let body = parser.parse_rule_body().ok();
+            let mut docs = docs;
+            docs.extend(parse_many_l1!(parser, parse_doc_comment, Token::DocComment));
+            let body = match parser.peek_token() {
+                Some(Token::NumSign) => None,
+                _ => Some(parser.parse_rule_body()?),
+            };
             parser.expect(Token::NumSign)?;
             let name = parser.parse_ident()?;
-            Ok(NamedRuleBody { name, body })
+            Ok(NamedRuleBody { docs, name, body })
         })
     }
 
     pub fn parse_rule_body(&mut self) -> Result<N<RuleBody>> {
         self.parse_n(|parser| {
-            let body = parse_some_l1!(parser, parse_rule_element, Token::Ident);
+            let mut body = vec![];
+            while let Some(Token::Ident)
+            | Some(Token::LParen)
+            | Some(Token::LitString)
+            | Some(Token::DocComment) = parser.peek_token()
+            {
+                body.push(parser.parse_rule_element()?);
+            }
+            if body.is_empty() {
+                // report what was found instead of an element
+                parser.expect(Token::Ident)?;
+            }
             Ok(RuleBody { body })
         })
     }
 
     pub fn parse_rule_element(&mut self) -> Result<N<RuleElement>> {
         self.parse_n(|parser| {
-            let name = parser.parse_ident()?;
-            let (name, nt) = if parser.advance_cmp(Token::Assign) {
-                let nt = parser.parse_ident()?;
-                (Some(name), nt)
-            } else {
-                (None, name)
+            let docs = parse_many_l1!(parser, parse_doc_comment, Token::DocComment);
+            let starts = [Token::Ident, Token::LParen, Token::LitString];
+            parser.expect_documented(&docs, &starts, "a rule element")?;
+            let term = parser.parse_term()?;
+            // only an identifier can be a label, `(a b)=c` is not valid
+            let (name, term) = match term {
+                Term::Ident(name) => {
+                    if parser.advance_cmp(Token::Assign) {
+                        (Some(name), parser.parse_term()?)
+                    } else {
+                        (None, Term::Ident(name))
+                    }
+                }
+                term => (None, term),
+            };
+            let quantifier = match parser.peek_token() {
+                Some(Token::Question)
+                | Some(Token::Asterisk)
+                | Some(Token::Plus)
+                | Some(Token::Percent) => Some(parser.parse_quantifier()?),
+                _ => None,
             };
-            let quantifier = parser.parse_quantifier().ok();
             Ok(RuleElement {
+                docs,
                 name,
-                nt,
+                term,
                 quantifier,
             })
         })
     }
 
+    pub fn parse_term(&mut self) -> Result<Term> {
+        match self.peek_token() {
+            Some(Token::LParen) => Ok(Term::Group(self.parse_group()?)),
+            Some(Token::LitString) => Ok(Term::Literal(self.parse_literal()?)),
+            _ => Ok(Term::Ident(self.parse_ident()?)),
+        }
+    }
+
+    pub fn parse_literal(&mut self) -> Result<N<Literal>> {
+        self.parse_n(|parser| {
+            let text = parser.parse_lit_string()?;
+            Ok(Literal { text })
+        })
+    }
+
+    pub fn parse_group(&mut self) -> Result<N<Group>> {
+        self.parse_n(|parser| {
+            parser.expect(Token::LParen)?;
+            let mut alts = vec![parser.parse_group_alt(1)?];
+            loop {
+                expect_one_of! { parser,
+                    Token::Alt => {
+                        let alt = parser.parse_group_alt(alts.len() + 1)?;
+                        alts.push(alt);
+                    },
+                    Token::RParen => {
+                        break;
+                    }
+                };
+            }
+            Ok(Group { alts })
+        })
+    }
+
+    /// The `index`th alternative of a group, see `Group::default_alt_name`
+    /// for alternatives without `# name`.
+    pub fn parse_group_alt(&mut self, index: usize) -> Result<N<NamedRuleBody>> {
+        self.parse_n(|parser| {
+            let body = parser.parse_rule_body()?;
+            let name = if parser.advance_cmp(Token::NumSign) {
+                parser.parse_ident()?
+            } else {
+                let name = Group::default_alt_name(&body, index);
+                parser.make_node(Ident { name })
+            };
+            Ok(NamedRuleBody {
+                docs: vec![],
+                name,
+                body: Some(body),
+            })
+        })
+    }
+
     pub fn parse_quantifier(&mut self) -> Result<N<Quantifier>> {
         self.parse_n(|parser| {
             let quantifier = expect_one_of! { parser,
                 Token::Question => Quantifier::Maybe,
                 Token::Asterisk => Quantifier::Multi,
-                Token::Plus => Quantifier::AtLeastOne
+                Token::Plus => Quantifier::AtLeastOne,
+                // a bare `% ","` repeats at least once
+                Token::Percent => Quantifier::AtLeastOneSep(parser.parse_lit_string()?)
             };
+            let has_sep = matches!(quantifier, Quantifier::Multi | Quantifier::AtLeastOne);
+            if has_sep && parser.advance_cmp(Token::Percent) {
+                let sep = parser.parse_lit_string()?;
+                return Ok(match quantifier {
+                    Quantifier::Multi => Quantifier::MultiSep(sep),
+                    _ => Quantifier::AtLeastOneSep(sep),
+                });
+            }
 
             Ok(quantifier)
         })
@@ -193,13 +467,109 @@ impl Parser {
     }
 
     pub fn parse_attr(&mut self) -> Result<N<Attr>> {
+        self.parse_n(|parser| parser.parse_attr_inner(Token::Attr))
+    }
+
+    /// `@!name(...)`, an attribute applying to the whole grammar.
+    pub fn parse_inner_attr(&mut self) -> Result<N<Attr>> {
+        self.parse_n(|parser| parser.parse_attr_inner(Token::InnerAttr))
+    }
+
+    fn parse_attr_inner(&mut self, tok: Token) -> Result<Attr> {
+        let d = self.expect(tok)?;
+        let name = self.chars().get_string(d.span).unwrap();
+        let name = name.trim_start_matches(&['@', '!'][..]).to_string();
+        let mut args = vec![];
+        if self.advance_cmp(Token::LParen) {
+            while !self.advance_cmp(Token::RParen) {
+                args.push(self.parse_attr_arg()?);
+                if !self.advance_cmp(Token::Comma) {
+                    self.expect(Token::RParen)?;
+                    break;
+                }
+            }
+        }
+        Ok(Attr { name, args })
+    }
+
+    pub fn parse_attr_arg(&mut self) -> Result<N<AttrArg>> {
         self.parse_n(|parser| {
-            let d = parser.expect(Token::Attr)?;
-            let name = parser.chars().get_string(d.span).unwrap();
-            let name = name.trim_matches('@').to_string();
-            Ok(Attr { name })
+            let (key, value) = sat_one_of! { parser,
+                Token::LitString => {
+                    (None, AttrValue::Str(parser.parse_lit_string()?))
+                },
+                Token::Ident => {
+                    let path = parser.parse_path()?;
+                    if parser.advance_cmp(Token::Assign) {
+                        (Some(path), parser.parse_attr_value()?)
+                    } else {
+                        (None, AttrValue::Path(path))
+                    }
+                }
+            };
+            Ok(AttrArg { key, value })
         })
     }
+
+    pub fn parse_attr_value(&mut self) -> Result<AttrValue> {
+        if let Some(Token::LitString) = self.peek_token() {
+            Ok(AttrValue::Str(self.parse_lit_string()?))
+        } else {
+            Ok(AttrValue::Path(self.parse_path()?))
+        }
+    }
+
+    /// `a::b::c`, an identifier is a path of one segment.
+    pub fn parse_path(&mut self) -> Result<String> {
+        let mut path = self.parse_ident()?.to_str().to_string();
+        while self.advance_cmp(Token::Colon) {
+            self.expect(Token::Colon)?;
+            path.push_str("::");
+            path.push_str(self.parse_ident()?.to_str());
+        }
+        Ok(path)
+    }
+
+    pub fn parse_lit_string(&mut self) -> Result<String> {
+        let d = self.expect(Token::LitString)?;
+        let s = self.chars().get_string(d.span).unwrap();
+        Ok(unescape(&s[1..s.len() - 1]))
+    }
+
+    pub fn parse_doc_comment(&mut self) -> Result<N<DocComment>> {
+        self.parse_n(|parser| {
+            let d = parser.expect(Token::DocComment)?;
+            let text = parser.chars().get_string(d.span).unwrap();
+            let text = text.trim_end_matches('\r')[3..].to_string();
+            let text = match text.strip_prefix(' ') {
+                Some(s) => s.to_string(),
+                None => text,
+            };
+            Ok(DocComment { text })
+        })
+    }
+
+    fn peek_token(&self) -> Option<Token> {
+        self.peek().map(|t| t.tok)
+    }
+
+    /// Points at `docs` when the next token can not start `what`, a doc
+    /// comment documents what follows it.
+    fn expect_documented(
+        &self,
+        docs: &[N<DocComment>],
+        starts: &[Token],
+        what: &'static str,
+    ) -> Result<()> {
+        let is_followed = matches!(self.peek_token(), Some(t) if starts.contains(&t));
+        match docs.first() {
+            Some(d) if !is_followed => {
+                let kind = ParseErrorKind::DanglingDocComment(what);
+                Err(self.make_error(Some(d.span), kind))
+            }
+            _ => Ok(()),
+        }
+    }
 }
 
 impl Parse for Parser {
@@ -215,13 +585,9 @@ impl Parse for Parser {
     }
 
     fn expect_err(&self, expected: Self::Token, found: Option<S<Self::Token>>) -> Self::Error {
-        let span = if let Some(d) = &found {
-            Some(d.span)
-        } else {
-            None
-        };
+        let span = found.map(|d| d.span);
         let kind = ParseErrorKind::UnexpectedToken(expected, found);
-        ParseError { span, kind }
+        self.make_error(span, kind)
     }
 
     fn expect_one_of_err(
@@ -229,12 +595,160 @@ impl Parse for Parser {
         expected: &[Self::Token],
         found: Option<S<Self::Token>>,
     ) -> Self::Error {
-        let span = if let Some(d) = &found {
-            Some(d.span)
-        } else {
-            None
-        };
-        let kind = ParseErrorKind::UnexpectedTokenMulti(expected.map(|t| t.clone()), found);
-        ParseError { span, kind }
+        let span = found.map(|d| d.span);
+        let kind = ParseErrorKind::UnexpectedTokenMulti(expected.map(|t| *t), found);
+        self.make_error(span, kind)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_err(s: &str) -> ParseError {
+        Parser::new(Lexer::new(s))
+            .and_then(|mut p| p.parse_grammar())
+            .unwrap_err()
+    }
+
+    #[test]
+    fn test_lex_error_location() {
+        let e = parse_err("a: b;\nc: 1d;");
+        assert!(matches!(e.kind(), ParseErrorKind::LexError(_)));
+        assert!(e.span().is_some());
+        let msg = e.to_string();
+        assert!(msg.starts_with("failed to tokenize grammar: 2:"), "{}", msg);
+        assert!(msg.contains("name can not start with digit `1`"), "{}", msg);
+        assert!(msg.contains("2 | c: 1d;"), "{}", msg);
+    }
+
+    fn parse(s: &str) -> N<Grammar> {
+        Parser::new(Lexer::new(s)).unwrap().parse_grammar().unwrap()
+    }
+
+    fn doc_texts(docs: &[N<DocComment>]) -> Vec<&str> {
+        docs.iter().map(|d| d.text.as_str()).collect()
+    }
+
+    #[test]
+    fn test_alt_docs() {
+        let s = "a /// x1\n: /// x2\nb # x\n/// y1\n| /// y2\n# y ;";
+        let grammar = parse(s);
+        match &grammar.rules[0].kind {
+            RuleKind::Enum(alts) => {
+                assert_eq!(doc_texts(&alts[0].docs), vec!["x1", "x2"]);
+                assert_eq!(doc_texts(&alts[1].docs), vec!["y1", "y2"]);
+                assert!(alts[0].body.as_ref().unwrap().body[0].docs.is_empty());
+            }
+            _ => panic!("a rule with `#` is an enum"),
+        }
+
+        // a struct has no alternative, the docs go to the rule and element
+        let grammar = parse("a /// r\n: /// b\nb ;");
+        assert_eq!(doc_texts(&grammar.rules[0].docs), vec!["r"]);
+        match &grammar.rules[0].kind {
+            RuleKind::Normal(b) => assert_eq!(doc_texts(&b.body[0].docs), vec!["b"]),
+            _ => panic!("a rule without `#` is a struct"),
+        }
+    }
+
+    #[test]
+    fn test_dangling_docs() {
+        let cases = [
+            ("a : b /// d\n;", "a rule element"),
+            ("a : b /// d\n# x ;", "a rule element"),
+            ("a : b # x | c /// d\n# y ;", "a rule element"),
+            ("a : (b /// d\n) ;", "a rule element"),
+            ("a : b ;\n/// d\n", "a rule"),
+            ("/// d\n;", "a rule"),
+        ];
+        for (s, what) in cases.iter() {
+            let e = parse_err(s);
+            let msg = format!("doc comment is not followed by {}", what);
+            assert_eq!(e.to_diagnostic().message, msg, "{}", s);
+            assert_eq!(e.span().map(|d| d.start), s.find("///"), "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_attr_args() {
+        let grammar = parse("@derive(PartialEq, serde::Serialize) @attr(\"a\") a : b ;");
+        let args: Vec<_> = grammar.rules[0]
+            .attrs
+            .iter()
+            .flat_map(|a| a.args.iter().map(|a| a.value.clone()))
+            .collect();
+        assert_eq!(
+            args,
+            vec![
+                AttrValue::Path("PartialEq".to_string()),
+                AttrValue::Path("serde::Serialize".to_string()),
+                AttrValue::Str("a".to_string()),
+            ]
+        );
+
+        // keys are checked when lowering, not parsing
+        let grammar = parse("@derive(a = b::c, d = \"e\") a : b ;");
+        let args: Vec<_> = grammar.rules[0].attrs[0]
+            .args
+            .iter()
+            .map(|a| (a.key.as_deref(), a.value.clone()))
+            .collect();
+        assert_eq!(
+            args,
+            vec![
+                (Some("a"), AttrValue::Path("b::c".to_string())),
+                (Some("d"), AttrValue::Str("e".to_string())),
+            ]
+        );
+        assert!(parse_err("@derive(a = ) a : b ;")
+            .to_string()
+            .contains("found `)`"));
+    }
+
+    #[test]
+    fn test_labels() {
+        let grammar = parse("a : left=expr op right=expr* # bin ;");
+        let body = grammar.rules[0].bodies()[0];
+        let labels: Vec<_> = body
+            .body
+            .iter()
+            .map(|e| e.name.as_ref().map(|n| n.to_str()))
+            .collect();
+        assert_eq!(labels, vec![Some("left"), None, Some("right")]);
+        let fields: Vec<_> = body.body.iter().map(|e| e.field_name()).collect();
+        assert_eq!(fields, vec!["left", "op", "rights"]);
+        assert!(body.body[2].quantifier.is_some());
+
+        // only an identifier can be a label
+        assert!(parse_err("a : (b c)=d ;").to_string().contains("found `=`"));
+        assert!(parse_err("a : \"b\"=d ;").to_string().contains("found `=`"));
+    }
+
+    #[test]
+    fn test_separators() {
+        let grammar = parse(r#"a : b* % "," c+ % ";" d % "\n" e+ ;"#);
+        let quantifiers: Vec<_> = grammar.rules[0].bodies()[0]
+            .body
+            .iter()
+            .map(|e| e.quantifier.as_ref().map(|q| q.data.clone()))
+            .collect();
+        assert_eq!(
+            quantifiers,
+            vec![
+                Some(Quantifier::MultiSep(",".to_string())),
+                Some(Quantifier::AtLeastOneSep(";".to_string())),
+                Some(Quantifier::AtLeastOneSep("\n".to_string())),
+                Some(Quantifier::AtLeastOne),
+            ]
+        );
+
+        // only repetitions have a separator, and it is a literal
+        assert!(parse_err(r#"a : b? % "," ;"#)
+            .to_string()
+            .contains("found `%`"));
+        assert!(parse_err("a : b* % c ;")
+            .to_string()
+            .contains("found identifier"));
     }
 }
@@ -1,16 +1,25 @@
+use std::fmt;
+
 #[derive(Clone, Debug, Copy, Eq, PartialEq)]
 pub enum Token {
     Question,
     Plus,
     Asterisk,
+    Percent,
     Colon,
     Semicolon,
     NumSign,
     Alt,
     Assign,
+    LParen,
+    RParen,
+    Comma,
     Ident,
     Attr,
+    InnerAttr,
     Whitespace(Whitespace),
+    Comment(Comment),
+    DocComment,
     LitString,
 }
 
@@ -22,3 +31,38 @@ pub enum Whitespace {
     CarriageReturn,
     HorizontalTab,
 }
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Comment {
+    /// `// ...`
+    Line,
+    /// `/* ... */`
+    Block,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Token::Question => "`?`",
+            Token::Plus => "`+`",
+            Token::Asterisk => "`*`",
+            Token::Percent => "`%`",
+            Token::Colon => "`:`",
+            Token::Semicolon => "`;`",
+            Token::NumSign => "`#`",
+            Token::Alt => "`|`",
+            Token::Assign => "`=`",
+            Token::LParen => "`(`",
+            Token::RParen => "`)`",
+            Token::Comma => "`,`",
+            Token::Ident => "identifier",
+            Token::Attr => "attribute",
+            Token::InnerAttr => "inner attribute",
+            Token::Whitespace(_) => "whitespace",
+            Token::Comment(_) => "comment",
+            Token::DocComment => "doc comment",
+            Token::LitString => "string literal",
+        };
+        f.write_str(s)
+    }
+}
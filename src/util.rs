@@ -1,36 +1,143 @@
-use iterable::Iterable;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use similar::TextDiff;
 
 pub fn is_std_primary(input: &str) -> bool {
-    match input {
-        "bool" | "u8" | "u16" | "u32" | "u64" | "u128" | "i8" | "i16" | "i32" | "i64" | "i128"
-        | "f32" | "f64" | "usize" | "isize" | "char" | "str" | "!" => true,
-        _ => false,
-    }
+    matches!(
+        input,
+        "bool"
+            | "u8"
+            | "u16"
+            | "u32"
+            | "u64"
+            | "u128"
+            | "i8"
+            | "i16"
+            | "i32"
+            | "i64"
+            | "i128"
+            | "f32"
+            | "f64"
+            | "usize"
+            | "isize"
+            | "char"
+            | "str"
+            | "!"
+    )
 }
 
-// TODO: add all keywords
+/// Strict and reserved keywords of Rust, `gen` included for the 2024
+/// edition.
+static KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true", "type",
+    "unsafe", "use", "where", "while", "abstract", "become", "box", "do", "final", "gen", "macro",
+    "override", "priv", "try", "typeof", "unsized", "virtual", "yield",
+];
+
 pub fn is_keyword(input: &str) -> bool {
+    KEYWORDS.contains(&input)
+}
+
+/// Types generated code refers to, a generated type can not take their name
+/// without shadowing them through the glob imports of the visitors.
+pub fn is_reserved_type(input: &str) -> bool {
+    matches!(
+        input,
+        "N" | "Box"
+            | "Vec"
+            | "Option"
+            | "Some"
+            | "None"
+            | "Sized"
+            | "String"
+            | "Self"
+            | "Visitor"
+            | "VisitorMut"
+    )
+}
+
+/// `input` as an identifier of generated code: keywords become raw
+/// identifiers, the ones that can not be raw (`self`, `Self`, `super`,
+/// `crate`) get a trailing `_`.
+pub fn rust_ident(input: &str) -> String {
     match input {
-        "as" | "break" | "where" => true,
+        "self" | "Self" | "super" | "crate" => format!("{}_", input),
+        _ if is_keyword(input) => format!("r#{}", input),
+        _ => input.to_string(),
+    }
+}
+
+/// Like `rust_ident`, reserved types also get a trailing `_`.
+pub fn rust_type_ident(input: &str) -> String {
+    if is_reserved_type(input) {
+        format!("{}_", input)
+    } else {
+        rust_ident(input)
+    }
+}
+
+/// Whether `rust_ident` or `rust_type_ident` changed `input` into another
+/// name, not just a raw identifier.
+pub fn is_renamed(input: &str, ident: &str) -> bool {
+    ident.trim_start_matches("r#") != input
+}
+
+/// Whether `input` can be used as a rule or variant name.
+pub fn is_ident(input: &str) -> bool {
+    let mut chars = input.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
         _ => false,
     }
 }
 
-pub fn trim(s: &str) -> String {
-    s.trim().to_string()
+/// Whether `input` is only underscores, which no generated name can be.
+pub fn is_underscore(input: &str) -> bool {
+    input.chars().all(|c| c == '_')
 }
 
-pub fn indent(s: &str) -> String {
-    let mut ret = vec![];
-    for l in s.lines() {
-        let d = if l.trim().is_empty() {
-            String::new()
-        } else {
-            format!("    {}", l)
-        };
-        ret.push(d)
+/// Resolves the backslash escapes of a string literal, `\n` and `\t` are
+/// control characters and any other escaped char stands for itself.
+pub fn unescape(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            ret.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => ret.push('\n'),
+            Some('t') => ret.push('\t'),
+            Some(c) => ret.push(c),
+            None => ret.push('\\'),
+        }
     }
-    ret.join("\n")
+    ret
+}
+
+/// The inverse of `unescape`, for printing a string literal.
+pub fn escape(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\n' => ret.push_str("\\n"),
+            '\t' => ret.push_str("\\t"),
+            '"' | '\\' => {
+                ret.push('\\');
+                ret.push(c);
+            }
+            _ => ret.push(c),
+        }
+    }
+    ret
 }
 
 pub fn camel_case(s: &str) -> String {
@@ -50,6 +157,112 @@ pub fn camel_case(s: &str) -> String {
     buf.into_iter().collect()
 }
 
+pub fn snake_case(s: &str) -> String {
+    let mut buf = String::with_capacity(s.len());
+    for (i, c) in s.chars().enumerate() {
+        if c.is_uppercase() {
+            if i != 0 && !buf.ends_with('_') {
+                buf.push('_');
+            }
+            buf.extend(c.to_lowercase());
+        } else {
+            buf.push(c);
+        }
+    }
+    buf
+}
+
+/// Levenshtein distance between two strings, counted in chars.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<_> = b.chars().collect();
+    let mut prev: Vec<_> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            cur[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+/// Picks the candidate closest to `name`, if it is close enough to be a typo.
+pub fn did_you_mean<'a, I>(name: &str, candidates: I) -> Option<&'a str>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let max = (name.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .filter(|c| *c != name)
+        .map(|c| (edit_distance(name, c), c))
+        .filter(|(d, _)| *d <= max)
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, c)| c)
+}
+
+/// Writes `contents` to `path` unless it already holds them, so the mtime of
+/// an unchanged file is kept. Returns whether the file was written.
+pub fn write_if_changed(path: &Path, contents: &str) -> io::Result<bool> {
+    match fs::read(path) {
+        Ok(d) if d == contents.as_bytes() => return Ok(false),
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    fs::write(path, contents)?;
+    Ok(true)
+}
+
+/// `path` relative to the directory `base` with `/` separators, e.g.
+/// `../grammars/sql.yp` for `grammars/sql.yp` from `src`. `path` as given
+/// when the two share no root, e.g. on different drives.
+pub fn relative_path(path: &Path, base: &Path) -> String {
+    let path_abs = absolute(path);
+    let base_abs = absolute(base);
+    let path_parts: Vec<_> = path_abs.components().collect();
+    let base_parts: Vec<_> = base_abs.components().collect();
+    let common = path_parts
+        .iter()
+        .zip(&base_parts)
+        .take_while(|(a, b)| a == b)
+        .count();
+    if common == 0 {
+        return path.display().to_string();
+    }
+    let ups = base_parts[common..].iter().map(|_| "..".to_string());
+    let downs = path_parts[common..]
+        .iter()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned());
+    ups.chain(downs).collect::<Vec<_>>().join("/")
+}
+
+/// `path` without symbolic links, not every path exists yet so only its
+/// longest existing ancestor is resolved.
+fn absolute(path: &Path) -> PathBuf {
+    let path = match env::current_dir() {
+        Ok(d) => d.join(path),
+        Err(_) => path.to_path_buf(),
+    };
+    for a in path.ancestors() {
+        if let Ok(d) = fs::canonicalize(a) {
+            // `a` is an ancestor of `path`
+            return d.join(path.strip_prefix(a).unwrap());
+        }
+    }
+    path
+}
+
+/// `old` and `new` contents of the file `name` as a unified diff, empty when
+/// they are equal.
+pub fn unified_diff(name: &str, old: &str, new: &str) -> String {
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .header(name, name)
+        .to_string()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -61,4 +274,86 @@ mod tests {
         assert_eq!("AbAb", camel_case("aB_ab"));
         assert_eq!("AbAb", camel_case("aB_aB"));
     }
+
+    #[test]
+    fn test_rust_ident() {
+        assert_eq!("expr", rust_ident("expr"));
+        assert_eq!("r#type", rust_ident("type"));
+        assert_eq!("self_", rust_ident("self"));
+        assert_eq!("Self_", rust_type_ident("Self"));
+        assert_eq!("Box_", rust_type_ident("Box"));
+        assert!(!is_renamed("type", "r#type"));
+        assert!(is_renamed("self", "self_"));
+    }
+
+    #[test]
+    fn test_write_if_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let path = dir.join("ast.rs");
+        assert!(write_if_changed(&path, "a").unwrap());
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        assert!(!write_if_changed(&path, "a").unwrap());
+        assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), modified);
+        assert!(write_if_changed(&path, "b").unwrap());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "b");
+        assert!(write_if_changed(&dir.join("no/such/dir"), "a").is_err());
+    }
+
+    #[test]
+    fn test_relative_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        fs::create_dir(dir.join("src")).unwrap();
+        let grammar = dir.join("grammars/sql.yp");
+        assert_eq!(relative_path(&grammar, dir), "grammars/sql.yp");
+        assert_eq!(
+            relative_path(&grammar, &dir.join("src")),
+            "../grammars/sql.yp"
+        );
+        assert_eq!(
+            relative_path(&grammar, &dir.join("src/./")),
+            "../grammars/sql.yp"
+        );
+    }
+
+    #[test]
+    fn test_is_ident() {
+        assert!(is_ident("select"));
+        assert!(is_ident("_a1"));
+        assert!(!is_ident("1a"));
+        assert!(!is_ident("<="));
+        assert!(!is_ident(""));
+    }
+
+    #[test]
+    fn test_escape() {
+        let s = "a \"b\" \\ \n";
+        assert_eq!(r#"a \"b\" \\ \n"#, escape(s));
+        assert_eq!(s, unescape(&escape(s)));
+    }
+
+    #[test]
+    fn test_snake_case() {
+        assert_eq!("ab", snake_case("ab"));
+        assert_eq!("select_item", snake_case("SelectItem"));
+        assert_eq!("select_item", snake_case("select_Item"));
+    }
+
+    #[test]
+    fn test_did_you_mean() {
+        assert_eq!(2, edit_distance("expr", "exp_r_"));
+        let rules = vec!["expr", "query", "relation"];
+        assert_eq!(Some("expr"), did_you_mean("exprr", rules.clone()));
+        assert_eq!(Some("relation"), did_you_mean("relaton", rules.clone()));
+        assert_eq!(None, did_you_mean("select", rules));
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        assert_eq!("", unified_diff("ast.rs", "a\n", "a\n"));
+        let d = unified_diff("ast.rs", "a\nb\n", "a\nc\n");
+        assert!(d.starts_with("--- ast.rs\n+++ ast.rs\n@@ -1,2 +1,2 @@\n"));
+        assert!(d.ends_with(" a\n-b\n+c\n"));
+    }
 }
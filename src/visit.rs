@@ -23,6 +23,14 @@ pub trait Visitor<'ast>: Sized {
         walk_rule_element(self, n)
     }
 
+    fn visit_group(&mut self, n: &'ast N<Group>) {
+        walk_group(self, n)
+    }
+
+    fn visit_literal(&mut self, n: &'ast N<Literal>) {
+        walk_literal(self, n)
+    }
+
     fn visit_quantifier(&mut self, n: &'ast N<Quantifier>) {
         walk_quantifier(self, n)
     }
@@ -34,6 +42,10 @@ pub trait Visitor<'ast>: Sized {
     fn visit_attr(&mut self, n: &'ast N<Attr>) {
         walk_attr(self, n)
     }
+
+    fn visit_doc_comment(&mut self, n: &'ast N<DocComment>) {
+        walk_doc_comment(self, n)
+    }
 }
 
 macro_rules! walk_list {
@@ -45,19 +57,23 @@ macro_rules! walk_list {
 }
 
 pub fn walk_grammar<'a, V: Visitor<'a>>(v: &mut V, n: &'a N<Grammar>) {
+    walk_list!(v, visit_attr, &n.attrs);
     walk_list!(v, visit_rule, &n.rules);
 }
 
 pub fn walk_rule<'a, V: Visitor<'a>>(v: &mut V, n: &'a N<Rule>) {
+    walk_list!(v, visit_doc_comment, &n.docs);
     walk_list!(v, visit_attr, &n.attrs);
     v.visit_ident(&n.name);
     match &n.kind {
         RuleKind::Enum(b) => walk_list!(v, visit_named_rule_body, b),
         RuleKind::Normal(b) => v.visit_rule_body(b),
+        RuleKind::Leaf => {}
     }
 }
 
 pub fn walk_named_rule_body<'a, V: Visitor<'a>>(v: &mut V, n: &'a N<NamedRuleBody>) {
+    walk_list!(v, visit_doc_comment, &n.docs);
     v.visit_ident(&n.name);
     if let Some(d) = &n.body {
         v.visit_rule_body(d);
@@ -69,17 +85,30 @@ pub fn walk_rule_body<'a, V: Visitor<'a>>(v: &mut V, n: &'a N<RuleBody>) {
 }
 
 pub fn walk_rule_element<'a, V: Visitor<'a>>(v: &mut V, n: &'a N<RuleElement>) {
+    walk_list!(v, visit_doc_comment, &n.docs);
     if let Some(d) = &n.name {
         v.visit_ident(d);
     }
-    v.visit_ident(&n.nt);
+    match &n.term {
+        Term::Ident(d) => v.visit_ident(d),
+        Term::Group(d) => v.visit_group(d),
+        Term::Literal(d) => v.visit_literal(d),
+    }
     if let Some(q) = &n.quantifier {
         v.visit_quantifier(q);
     }
 }
 
+pub fn walk_group<'a, V: Visitor<'a>>(v: &mut V, n: &'a N<Group>) {
+    walk_list!(v, visit_named_rule_body, &n.alts)
+}
+
+pub fn walk_literal<'a, V: Visitor<'a>>(_v: &mut V, _n: &'a N<Literal>) {}
+
 pub fn walk_quantifier<'a, V: Visitor<'a>>(_v: &mut V, _n: &'a N<Quantifier>) {}
 
 pub fn walk_ident<'a, V: Visitor<'a>>(_v: &mut V, _n: &'a N<Ident>) {}
 
 pub fn walk_attr<'a, V: Visitor<'a>>(_v: &mut V, _n: &'a N<Attr>) {}
+
+pub fn walk_doc_comment<'a, V: Visitor<'a>>(_v: &mut V, _n: &'a N<DocComment>) {}